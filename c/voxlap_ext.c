@@ -0,0 +1,31 @@
+// Accessors used by the Rust binding (see the "custom" block of src/c_api.rs)
+// for engine state which libvoxlap does not export by itself. Compile and link
+// this file together with voxlap5.c.
+
+#include "voxlap5.h"
+
+extern long zbufoff;
+
+long get_zbufoff (void) { return(zbufoff); }
+
+long get_maxscandist (void) { return(vx5.maxscandist); }
+long get_lightmode (void) { return(vx5.lightmode); }
+long get_fogcol (void) { return(vx5.fogcol); }
+long get_kv6col (void) { return(vx5.kv6col); }
+void set_mipscandist (long mipscandist) { vx5.mipscandist = mipscandist; }
+long get_mipscandist (void) { return(vx5.mipscandist); }
+void set_vxlmipuse (long vxlmipuse) { vx5.vxlmipuse = vxlmipuse; }
+long get_vxlmipuse (void) { return(vx5.vxlmipuse); }
+void set_kv6mipfactor (long kv6mipfactor) { vx5.kv6mipfactor = kv6mipfactor; }
+long get_kv6mipfactor (void) { return(vx5.kv6mipfactor); }
+void set_xplanemin (long xplanemin) { vx5.xplanemin = xplanemin; }
+long get_xplanemin (void) { return(vx5.xplanemin); }
+void set_xplanemax (long xplanemax) { vx5.xplanemax = xplanemax; }
+long get_xplanemax (void) { return(vx5.xplanemax); }
+
+lightsrctype *get_lightsrc (void) { return(vx5.lightsrc); }
+void set_numlights (long numlights) { vx5.numlights = numlights; }
+long get_numlights (void) { return(vx5.numlights); }
+
+void set_currad (long currad) { vx5.currad = currad; }
+long get_currad (void) { return(vx5.currad); }
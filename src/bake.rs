@@ -0,0 +1,202 @@
+use c_api;
+use {ivec3, vec3, VisibilityResult, VoxelColor, Voxlap};
+
+const NEUTRAL_INTENSITY: u8 = VoxelColor::NEUTRAL_INTENSITY;
+
+// ---- Flash lighting: ----
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FlashKind {
+    /// `setflash`, `num_angles` is the angle density (512, 1024 or 2048 are recommended)
+    Flash {num_angles: i32},
+    /// `setnormflash`, uses the estimated normals of the voxels
+    NormFlash,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct FlashLight {
+    pub pos: vec3,
+    /// Maximum radius to scan out (128-253 are recommended)
+    pub radius: i32,
+    /// Intensity scale (1 or 2 are recommended)
+    pub intensity: i32,
+    pub kind: FlashKind,
+}
+
+impl FlashLight {
+    pub fn new(pos: &vec3, radius: i32, intensity: i32, kind: FlashKind) -> FlashLight {
+        FlashLight {
+            pos: *pos,
+            radius: radius,
+            intensity: intensity,
+            kind: kind,
+        }
+    }
+}
+
+/// Adds the light of every source to the intensity bytes of the map.
+/// `progress` is called with the number of finished lights and the total
+/// after each light. Save the result with `Voxlap::save_vxl`.
+pub fn bake_flash_lights<F>(vox: &mut Voxlap, lights: &[FlashLight], mut progress: F)
+    where F: FnMut(usize, usize) {
+    for (i, light) in lights.iter().enumerate() {
+        match light.kind {
+            FlashKind::Flash {num_angles} => vox.set_flash(&light.pos, light.radius, num_angles, light.intensity),
+            FlashKind::NormFlash => vox.set_norm_flash(&light.pos, light.radius, light.intensity),
+        }
+        progress(i + 1, lights.len());
+    }
+}
+
+/// Sets the intensity byte of every surface voxel between `min` and `max`
+/// (inclusive) back to `VoxelColor::NEUTRAL_INTENSITY`.
+pub fn reset_intensity(vox: &mut Voxlap, min: &ivec3, max: &ivec3) {
+    for_each_surface_voxel(vox, min, max, |_, _| NEUTRAL_INTENSITY);
+}
+
+/// Calls `f` with the position and intensity of every surface voxel in the
+/// box (inclusive) and stores the intensity it returns.
+fn for_each_surface_voxel<F>(vox: &Voxlap, min: &ivec3, max: &ivec3, mut f: F)
+    where F: FnMut(&ivec3, u8) -> u8 {
+    let size = vox.get_max_xy_dimension();
+    for y in min.y.max(0) ..= max.y.min(size - 1) {
+        for x in min.x.max(0) ..= max.x.min(size - 1) {
+            for z in min.z.max(0) ..= max.z.min(255) {
+                unsafe {
+                    // 0 is air, 1 is an unexposed solid voxel without color
+                    let color = c_api::getcube(x, y, z);
+                    if color == 0 || color == 1 {
+                        continue;
+                    }
+                    let color = color as *mut u32;
+                    let intensity = f(&ivec3::new(x, y, z), (*color >> 24) as u8);
+                    *color = (*color & 0x00FFFFFF) | ((intensity as u32) << 24);
+                }
+            }
+        }
+    }
+}
+
+// ---- Ambient occlusion: ----
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct AoSettings {
+    /// Rays per voxel (on the hemisphere above the surface)
+    pub samples: u32,
+    /// Length of the rays in voxels
+    pub radius: f32,
+    /// 0..1, how dark a fully occluded voxel gets
+    pub strength: f32,
+}
+
+impl Default for AoSettings {
+    fn default() -> AoSettings {
+        AoSettings {
+            samples: 32,
+            radius: 8f32,
+            strength: 0.75f32,
+        }
+    }
+}
+
+/// Writes ambient occlusion into the intensity byte of every surface voxel
+/// between `min` and `max` (inclusive), replacing the previous intensity. The
+/// rays may leave the box, so rerunning it on the box of an edit (grown by
+/// `radius`) updates the neighbourhood too. `progress` is called with the
+/// number of finished rows (y) and the total.
+pub fn bake_ambient_occlusion<F>(vox: &mut Voxlap, min: &ivec3, max: &ivec3, settings: &AoSettings, progress: F)
+    where F: FnMut(usize, usize) {
+    // Evenly spread directions on the whole sphere, every voxel uses the half above its surface
+    let directions = fibonacci_sphere(settings.samples as usize * 2);
+    bake_surface_rays(vox, min, max, progress, |vox, normal, start| {
+        let mut total = 0f32;
+        let mut occluded = 0f32;
+        for dir in directions.iter() {
+            let weight = dir.dot(normal);
+            if weight <= 0f32 {
+                continue;
+            }
+            total += weight;
+            if let VisibilityResult::CannotSee(_) = vox.can_see(start, &(*start + *dir * settings.radius)) {
+                occluded += weight;
+            }
+        }
+        let occlusion = if total > 0f32 {occluded / total} else {0f32};
+        (NEUTRAL_INTENSITY as f32 * (1f32 - settings.strength * occlusion)).round() as u8
+    });
+}
+
+/// Row (y) by row, calls `f` with the estimated normal of every surface voxel
+/// in the box (inclusive) and the start of the rays cast from its surface,
+/// stores the intensity it returns and reports the finished rows to `progress`.
+fn bake_surface_rays<P, F>(vox: &mut Voxlap, min: &ivec3, max: &ivec3, mut progress: P, mut f: F)
+    where P: FnMut(usize, usize), F: FnMut(&Voxlap, &vec3, &vec3) -> u8 {
+    let vox: &Voxlap = vox;
+    let rows = (max.y - min.y + 1).max(0) as usize;
+    for (i, y) in (min.y ..= max.y).enumerate() {
+        let row_min = ivec3::new(min.x, y, min.z);
+        let row_max = ivec3::new(max.x, y, max.z);
+        for_each_surface_voxel(vox, &row_min, &row_max, |pos, _| {
+            let normal = vox.estimate_normal_vector(pos);
+            // to_vec3 is the center of the voxel
+            f(vox, &normal, &(pos.to_vec3() + normal))
+        });
+        progress(i + 1, rows);
+    }
+}
+
+fn fibonacci_sphere(count: usize) -> Vec<vec3> {
+    let golden_angle = ::std::f32::consts::PI * (3f32 - 5f32.sqrt());
+    (0 .. count).map(|i| {
+        let z = 1f32 - (i as f32 + 0.5f32) / count as f32 * 2f32;
+        let r = (1f32 - z * z).sqrt();
+        let (sin, cos) = (golden_angle * i as f32).sin_cos();
+        vec3::new(cos * r, sin * r, z)
+    }).collect()
+}
+
+// ---- Sun shadows: ----
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct SunSettings {
+    /// Normalized direction pointing towards the sun (z points down)
+    pub direction: vec3,
+    /// Intensity scale of the voxels lit by the sun, 1 is neutral
+    pub brightness: f32,
+    /// Intensity scale of the voxels in shadow
+    pub ambient: f32,
+    /// How far the shadow rays are cast
+    pub max_distance: f32,
+}
+
+impl Default for SunSettings {
+    fn default() -> SunSettings {
+        SunSettings {
+            direction: vec3::new(0.5f32, 0.3f32, -0.8f32).normalize(),
+            brightness: 1f32,
+            ambient: 0.5f32,
+            max_distance: 256f32,
+        }
+    }
+}
+
+/// Writes directional sunlight into the intensity byte of every surface
+/// voxel between `min` and `max` (inclusive), replacing the previous
+/// intensity: voxels facing away from the sun or with the map between them
+/// and the sun get `ambient`, the rest `brightness`. `progress` is called
+/// like in `bake_ambient_occlusion`.
+pub fn bake_sun_shadows<F>(vox: &mut Voxlap, min: &ivec3, max: &ivec3, sun: &SunSettings, progress: F)
+    where F: FnMut(usize, usize) {
+    let to_intensity = |scale: f32| (NEUTRAL_INTENSITY as f32 * scale).round().clamp(0f32, 255f32) as u8;
+    let lit_intensity = to_intensity(sun.brightness);
+    let shadow_intensity = to_intensity(sun.ambient);
+    bake_surface_rays(vox, min, max, progress, |vox, normal, start| {
+        if normal.dot(&sun.direction) <= 0f32 {
+            return shadow_intensity;
+        }
+        match vox.can_see(start, &(*start + sun.direction * sun.max_distance)) {
+            VisibilityResult::CanSee => lit_intensity,
+            VisibilityResult::CannotSee(_) => shadow_intensity,
+        }
+    });
+}
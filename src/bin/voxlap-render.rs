@@ -0,0 +1,222 @@
+//! Renders a VXL map or SXL scene from a list of cameras into PNG files.
+//!
+//!     voxlap-render [options] <map.vxl|scene.sxl> <cameras.json|cameras.toml>
+//!
+//! The camera file has a `camera` list, every camera has an `output` file
+//! name, a `pos` and either a `look_at` point or `yaw`/`pitch`/`roll` angles
+//! in degrees, and optionally its own `fov`. In TOML:
+//!
+//!     [[camera]]
+//!     output = "overview.png"
+//!     pos = [512.0, 512.0, 20.0]
+//!     look_at = [600.0, 600.0, 128.0]
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+extern crate voxlap;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process;
+
+use voxlap::{vec3, Color, LightingMode, Orientation, RenderDestination, Sprite, Voxlap};
+
+const USAGE: &str = "usage: voxlap-render [options] <map.vxl|scene.sxl> <cameras.json|cameras.toml>
+
+options:
+    --sky FILE              sky image, overrides the sky of an SXL scene
+    --size WIDTHxHEIGHT     resolution of the images (default 640x480)
+    --fov DEGREES           horizontal field of view (default 90)
+    --lighting MODE         none, normals or lights (default none)
+    --max-scan-dist VOXELS  view distance, also enables the fog
+    --fog RRGGBB            fog color (hex)
+    --out-dir DIR           directory of the images (default .)";
+
+#[derive(Deserialize)]
+struct CameraSpec {
+    output: String,
+    pos: [f32; 3],
+    look_at: Option<[f32; 3]>,
+    #[serde(default)]
+    yaw: f32,
+    #[serde(default)]
+    pitch: f32,
+    #[serde(default)]
+    roll: f32,
+    fov: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct CameraList {
+    camera: Vec<CameraSpec>,
+}
+
+struct Options {
+    map: String,
+    cameras: String,
+    sky: Option<String>,
+    width: u32,
+    height: u32,
+    fov: f32,
+    lighting: LightingMode,
+    max_scan_dist: Option<i32>,
+    fog: Option<Color>,
+    out_dir: String,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        map: String::new(),
+        cameras: String::new(),
+        sky: None,
+        width: 640,
+        height: 480,
+        fov: 90f32,
+        lighting: LightingMode::NoSpecialLighting,
+        max_scan_dist: None,
+        fog: None,
+        out_dir: ".".to_string(),
+    };
+    let mut positional = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.clone());
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+        let bad_value = || format!("bad value for {}: {}", arg, value);
+        match arg.as_str() {
+            "--sky" => options.sky = Some(value.clone()),
+            "--size" => {
+                let mut parts = value.split('x').map(|n| n.parse::<u32>());
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(w)), Some(Ok(h)), None) if w > 0 && h > 0 => {
+                        options.width = w;
+                        options.height = h;
+                    },
+                    _ => return Err(bad_value()),
+                }
+            },
+            "--fov" => options.fov = value.parse::<f32>().ok().filter(|fov| *fov > 0f32 && *fov < 180f32).ok_or_else(bad_value)?,
+            "--lighting" => options.lighting = match value.as_str() {
+                "none" => LightingMode::NoSpecialLighting,
+                "normals" => LightingMode::SimpleEstimatedNormalLighting,
+                "lights" => LightingMode::MultiplePointSourceLighting,
+                _ => return Err(bad_value()),
+            },
+            "--max-scan-dist" => options.max_scan_dist = Some(value.parse::<i32>().map_err(|_| bad_value())?),
+            "--fog" => {
+                let rgb = u32::from_str_radix(value.trim_start_matches('#'), 16).map_err(|_| bad_value())?;
+                options.fog = Some(Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+            },
+            "--out-dir" => options.out_dir = value.clone(),
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+    if positional.len() != 2 {
+        return Err("expected a map and a camera file".to_string());
+    }
+    options.cameras = positional.pop().unwrap();
+    options.map = positional.pop().unwrap();
+    Ok(options)
+}
+
+fn load_cameras(filename: &str) -> Result<Vec<CameraSpec>, String> {
+    let mut text = String::new();
+    File::open(filename)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("{}: {}", filename, e))?;
+    let list: CameraList = if filename.ends_with(".toml") {
+        toml::from_str(&text).map_err(|e| format!("{}: {}", filename, e))?
+    } else {
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", filename, e))?
+    };
+    if list.camera.is_empty() {
+        return Err(format!("{}: no cameras", filename));
+    }
+    Ok(list.camera)
+}
+
+fn orientation(spec: &CameraSpec) -> Orientation {
+    let pos = vec3::new(spec.pos[0], spec.pos[1], spec.pos[2]);
+    match spec.look_at {
+        Some(target) => Orientation::look_at(&pos, &vec3::new(target[0], target[1], target[2]), &vec3::new(0f32, 0f32, -1f32)),
+        None => Orientation::from_yaw_pitch_roll(&pos, spec.yaw.to_radians(), spec.pitch.to_radians(), spec.roll.to_radians()),
+    }
+}
+
+fn run(options: &Options) -> Result<u32, String> {
+    let cameras = load_cameras(&options.cameras)?;
+    let mut vox = Voxlap::new().map_err(|_| "could not initialize Voxlap".to_string())?;
+
+    let mut sprites: Vec<Sprite> = vec![];
+    let mut sky = options.sky.clone();
+    if options.map.to_lowercase().ends_with(".sxl") {
+        let scene = vox.load_sxl(&options.map).map_err(|_| format!("could not load {}", options.map))?;
+        vox.load_vxl(&scene.vxl_file).map_err(|_| format!("could not load {}", scene.vxl_file))?;
+        if sky.is_none() && !scene.sky_file.is_empty() {
+            sky = Some(scene.sky_file.clone());
+        }
+        sprites = scene.sprites;
+    } else {
+        vox.load_vxl(&options.map).map_err(|_| format!("could not load {}", options.map))?;
+    }
+    if let Some(ref sky) = sky {
+        vox.load_sky(sky).map_err(|_| format!("could not load {}", sky))?;
+    }
+
+    match options.max_scan_dist {
+        Some(dist) => vox.set_max_scan_dist(dist),
+        None => vox.set_max_scan_dist_to_max(),
+    }
+    if let Some(fog) = options.fog {
+        vox.set_fog_color(fog);
+    }
+    let lit = options.lighting != LightingMode::NoSpecialLighting;
+    vox.set_lighting_mode(options.lighting);
+    if lit {
+        let size = vox.get_max_xy_dimension();
+        vox.update_lighting(0, 0, 0, size, size, 256);
+    }
+
+    let mut dst = RenderDestination::new(options.width, options.height);
+    for spec in cameras.iter() {
+        let fov = spec.fov.unwrap_or(options.fov);
+        let focal_length = 1f32 / (fov.to_radians() * 0.5f32).tan();
+        let output = Path::new(&options.out_dir).join(&spec.output);
+        let render_context = vox.set_frame_buffer(&mut dst);
+        render_context.set_camera(&orientation(spec), focal_length);
+        render_context.opticast();
+        for spr in sprites.iter() {
+            render_context.draw_sprite(spr);
+        }
+        render_context.save_to_file(&output.to_string_lossy());
+        println!("{}", output.display());
+    }
+    Ok(cameras.len() as u32)
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if args.is_empty() || args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
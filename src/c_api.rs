@@ -31,6 +31,20 @@ pub struct dpoint3d {
     pub z: c_double,
 }
 
+/// Size of vx5.lightsrc
+pub const MAXLIGHTS: usize = 256;
+
+/// Point light source of lighting mode 2
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct lightsrctype {
+    pub p: point3d,
+    /// Square of the radius
+    pub r2: c_float,
+    /// Intensity scale
+    pub sc: c_float,
+}
+
 #[repr(C)]
 pub struct kv6data {
     pub leng: c_long,
@@ -183,7 +197,7 @@ pub struct kfatype {
           x0: c_float, y0: c_float, x1: c_float, y1: c_float,
           x2: c_float, y2: c_float, x3: c_float, y3: c_float);
 
-        pub fn drawpolyquad (rpic: c_long, rbpl: c_ulong, rxsiz: c_ulong, rysiz: c_ulong,
+        pub fn drawpolyquad (rpic: *const u8, rbpl: c_ulong, rxsiz: c_ulong, rysiz: c_ulong,
          x0: c_float, y0: c_float, z0: c_float, u0: c_float, v0: c_float,
          x1: c_float, y1: c_float, z1: c_float, u1: c_float, v1: c_float,
          x2: c_float, y2: c_float, z2: c_float, u2: c_float, v2: c_float,
@@ -337,10 +351,10 @@ pub struct kfatype {
         /// -------------------------  Physics helper functions: -------------------------
         /// Math helper: The vectors are refreshed to be perpendicular to each other
         ///   and have unit length. v0 does not change orientation.
-        pub fn orthonormalize (v0: &point3d, v1: &point3d, v2: &point3d);
+        pub fn orthonormalize (v0: &mut point3d, v1: &mut point3d, v2: &mut point3d);
 
         /// Math helper: same as orthonormalize but for doubles
-        pub fn dorthonormalize (v0: &dpoint3d, v1: &dpoint3d, v2: &dpoint3d);
+        pub fn dorthonormalize (v0: &mut dpoint3d, v1: &mut dpoint3d, v2: &mut dpoint3d);
 
         /// Math helper: rotates 3 vectors using 3 Euclidian rotation
         /// ox: angle #1 (yaw)
@@ -350,11 +364,11 @@ pub struct kfatype {
         /// ihe: input&output: vector #2 to rotate
         /// ifo: input&output: vector #3 to rotate
         pub fn orthorotate (ox: c_float, oy: c_float, oz: c_float,
-            ist: &point3d, ihe: &point3d, ifo: &point3d);
+            ist: &mut point3d, ihe: &mut point3d, ifo: &mut point3d);
 
         /// Math helper: same as orthorotate but for doubles
         pub fn dorthorotate (ox: c_double, oy: c_double, oz: c_double,
-         ist: &dpoint3d, ihe: &dpoint3d, ifo: &dpoint3d);
+         ist: &mut dpoint3d, ihe: &mut dpoint3d, ifo: &mut dpoint3d);
 
         pub fn axisrotate(p: &mut point3d, axis: &point3d, w: c_float);
 
@@ -368,7 +382,7 @@ pub struct kfatype {
         ///                   0 means ist=istr, etc..., 1 means ist=istr2, etc...
         pub fn slerp (istr: &point3d, ihei: &point3d, ifor: &point3d,
             istr2: &point3d, ihei2: &point3d, ifor2: &point3d,
-            ist: &point3d, ihe: &point3d, ifo: &point3d, rat: &point3d);
+            ist: &mut point3d, ihe: &mut point3d, ifo: &mut point3d, rat: c_float);
 
         /// Detect if 2 points have a direct line-of-sight
         /// p0: starting point
@@ -609,7 +623,30 @@ pub struct kfatype {
         pub fn set_kv6col(kv6col: c_long);
         pub fn set_curcol(curcol: c_long);
         pub fn set_curpow(curpow: c_float);
+        pub fn set_currad(currad: c_long);
+        pub fn get_currad() -> c_long;
         pub fn set_fallcheck(fallcheck: c_long);
+        pub fn get_maxscandist() -> c_long;
+        pub fn get_lightmode() -> c_long;
+        pub fn get_fogcol() -> c_long;
+        pub fn get_kv6col() -> c_long;
+        pub fn set_mipscandist(mipscandist: c_long);
+        pub fn get_mipscandist() -> c_long;
+        pub fn set_vxlmipuse(vxlmipuse: c_long);
+        pub fn get_vxlmipuse() -> c_long;
+        pub fn set_kv6mipfactor(kv6mipfactor: c_long);
+        pub fn get_kv6mipfactor() -> c_long;
+        pub fn set_xplanemin(xplanemin: c_long);
+        pub fn get_xplanemin() -> c_long;
+        pub fn set_xplanemax(xplanemax: c_long);
+        pub fn get_xplanemax() -> c_long;
+        /// Pointer to vx5.lightsrc[0], MAXLIGHTS elements
+        pub fn get_lightsrc() -> *mut lightsrctype;
+        pub fn set_numlights(numlights: c_long);
+        pub fn get_numlights() -> c_long;
+        /// Byte offset of the Z-buffer from the frame buffer (zbufoff), the
+        /// Z-buffer has the same pitch as the frame buffer
+        pub fn get_zbufoff() -> c_long;
 
         /// ------------------------- Falling voxels functions: --------------------------
         /// NOTE: THIS FUNCTION IS OBSOLETE!
@@ -693,7 +730,7 @@ pub fn dofall (i: c_long);
         ///      bpl: pitch (bytes per line) of destination uncompressed image
         /// xsiz,ysiz: dimensions of destination image
         /// NOTE: You are responsible for calling free() on the returned pointer
-        pub fn kpzload (filnam: *const c_char, pic: *mut c_long, bpl: *mut c_ulong,
+        pub fn kpzload (filnam: *const c_char, pic: *mut *mut u8, bpl: *mut c_ulong,
           xsiz: *mut c_ulong, ysiz: *mut c_ulong);
 
         /// This retrieves the dimensions of a compressed graphic file image loaded
@@ -0,0 +1,181 @@
+use std::f32::consts::PI;
+
+use {vec3, Orientation, Voxlap};
+
+/// Abstract input for one update, usually mouse deltas and key states.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct CameraInput {
+    /// Horizontal look delta, positive turns right.
+    pub look_x: f32,
+    /// Vertical look delta, positive looks down (like mouse y).
+    pub look_y: f32,
+    /// Roll delta, only used by the free-fly camera.
+    pub roll: f32,
+    /// Movement in camera space: x is right, y is down, z is forward; usually -1..1 on each axis.
+    pub movement: vec3,
+    /// Positive zooms in, only used by the orbit camera.
+    pub zoom: f32,
+}
+
+/// 6DOF camera without collision, like the editor camera of Voxed.
+pub struct FreeFlyCamera {
+    pub ori: Orientation,
+    /// Voxels per second
+    pub speed: f32,
+    /// Radians per look input unit
+    pub sensitivity: f32,
+}
+
+impl FreeFlyCamera {
+    pub fn new(ori: Orientation) -> FreeFlyCamera {
+        FreeFlyCamera {
+            ori: ori,
+            speed: 64f32,
+            sensitivity: 0.008f32,
+        }
+    }
+
+    pub fn update(&mut self, input: &CameraInput, dt: f32) {
+        self.ori.rotate_local(input.roll * self.sensitivity, -input.look_y * self.sensitivity, input.look_x * self.sensitivity);
+        let dir = self.ori.right_vec * input.movement.x + self.ori.down_vec * input.movement.y + self.ori.forward_vec * input.movement.z;
+        self.ori.pos = self.ori.pos + dir * (self.speed * dt);
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.ori.clone()
+    }
+}
+
+/// Walking camera: yaw and clamped pitch, gravity and collision with the map
+/// through `clip_move`.
+pub struct FpsCamera {
+    pub pos: vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Maximum absolute pitch in radians
+    pub pitch_limit: f32,
+    pub speed: f32,
+    pub sensitivity: f32,
+    /// Radius of the collision sphere
+    pub radius: f64,
+    /// Acceleration in voxels/s², z points down
+    pub gravity: f32,
+    pub jump_speed: f32,
+    vertical_speed: f32,
+    on_ground: bool,
+}
+
+impl FpsCamera {
+    pub fn new(pos: vec3) -> FpsCamera {
+        FpsCamera {
+            pos: pos,
+            yaw: 0f32,
+            pitch: 0f32,
+            pitch_limit: PI * 0.49f32,
+            speed: 32f32,
+            sensitivity: 0.008f32,
+            radius: 3f64,
+            gravity: 64f32,
+            jump_speed: 24f32,
+            vertical_speed: 0f32,
+            on_ground: false,
+        }
+    }
+
+    pub fn is_on_ground(&self) -> bool {
+        self.on_ground
+    }
+
+    /// A negative `movement.y` (up) jumps when standing on the ground.
+    pub fn update(&mut self, vox: &Voxlap, input: &CameraInput, dt: f32) {
+        self.yaw += input.look_x * self.sensitivity;
+        self.pitch = (self.pitch - input.look_y * self.sensitivity).max(-self.pitch_limit).min(self.pitch_limit);
+
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let forward = vec3::new(cos_yaw, sin_yaw, 0f32);
+        let right = vec3::new(-sin_yaw, cos_yaw, 0f32);
+        let walk = (right * input.movement.x + forward * input.movement.z) * (self.speed * dt);
+        vox.clip_move(&mut self.pos, &walk, self.radius);
+
+        if self.on_ground && input.movement.y < 0f32 {
+            self.vertical_speed = -self.jump_speed;
+        }
+        self.vertical_speed += self.gravity * dt;
+        let fall = vec3::new(0f32, 0f32, self.vertical_speed * dt);
+        let before = self.pos.z;
+        vox.clip_move(&mut self.pos, &fall, self.radius);
+        let blocked = (self.pos.z - before - fall.z).abs() > 0.001f32;
+        self.on_ground = blocked && self.vertical_speed > 0f32;
+        if blocked {
+            self.vertical_speed = 0f32;
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        Orientation::from_yaw_pitch_roll(&self.pos, self.yaw, self.pitch, 0f32)
+    }
+}
+
+/// Camera circling around a target point. If the map is between the target
+/// and the camera, the camera is pulled in front of it.
+pub struct OrbitCamera {
+    pub target: vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub pitch_limit: f32,
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub sensitivity: f32,
+    /// Distance units per zoom input unit
+    pub zoom_speed: f32,
+    /// How far the camera stays from the map when it is pulled in
+    pub collision_margin: f32,
+    current_distance: f32,
+}
+
+impl OrbitCamera {
+    pub fn new(target: vec3, distance: f32) -> OrbitCamera {
+        OrbitCamera {
+            target: target,
+            yaw: 0f32,
+            pitch: PI * 0.25f32,
+            pitch_limit: PI * 0.49f32,
+            distance: distance,
+            min_distance: 1f32,
+            max_distance: 512f32,
+            sensitivity: 0.008f32,
+            zoom_speed: 4f32,
+            collision_margin: 1f32,
+            current_distance: distance,
+        }
+    }
+
+    pub fn update(&mut self, vox: &mut Voxlap, input: &CameraInput) {
+        self.yaw += input.look_x * self.sensitivity;
+        self.pitch = (self.pitch - input.look_y * self.sensitivity).max(-self.pitch_limit).min(self.pitch_limit);
+        self.distance = (self.distance - input.zoom * self.zoom_speed).max(self.min_distance).min(self.max_distance);
+
+        let back = Orientation::from_yaw_pitch_roll(&self.target, self.yaw, self.pitch, 0f32).forward_vec * -1f32;
+        self.current_distance = self.distance;
+        if let Some(hit) = vox.hitscan(&self.target, &back) {
+            let hit_dist = (hit.pos.to_vec3() - self.target).len() - self.collision_margin;
+            if hit_dist < self.current_distance {
+                self.current_distance = hit_dist.max(0f32);
+            }
+        }
+    }
+
+    /// The distance used by the last update, after the collision pull-in.
+    pub fn current_distance(&self) -> f32 {
+        self.current_distance
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        let ori = Orientation::from_yaw_pitch_roll(&self.target, self.yaw, self.pitch, 0f32);
+        Orientation {
+            pos: self.target - ori.forward_vec * self.current_distance,
+            ..ori
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use {vec3, Orientation, RenderContext, RenderDestination, Voxlap};
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Keyframe {
+    /// Seconds from the start of the path
+    pub time: f32,
+    pub ori: Orientation,
+}
+
+/// Timestamped camera keyframes, interpolated with a Catmull-Rom spline for
+/// the position and slerp for the rotation.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath {
+            keyframes: vec![],
+        }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Inserts a keyframe, keeping the keyframes ordered by time.
+    pub fn add_keyframe(&mut self, time: f32, ori: &Orientation) {
+        let index = self.keyframes.iter().position(|k| k.time > time).unwrap_or(self.keyframes.len());
+        self.keyframes.insert(index, Keyframe {
+            time: time,
+            ori: ori.clone(),
+        });
+    }
+
+    /// Appends the orientation if at least `min_interval` seconds passed since
+    /// the last keyframe. Call it every frame while recording.
+    pub fn record(&mut self, time: f32, ori: &Orientation, min_interval: f32) {
+        let due = match self.keyframes.last() {
+            None => true,
+            Some(last) => time - last.time >= min_interval,
+        };
+        if due {
+            self.add_keyframe(time, ori);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0f32, |k| k.time)
+    }
+
+    /// Orientation at the given time, clamped to the ends of the path. NaN
+    /// gives the first keyframe.
+    pub fn sample(&self, time: f32) -> Option<Orientation> {
+        let keys = &self.keyframes;
+        if keys.is_empty() {
+            return None;
+        }
+        if time <= keys[0].time || time.is_nan() {
+            return Some(keys[0].ori.clone());
+        }
+        let last = keys.len() - 1;
+        if time >= keys[last].time {
+            return Some(keys[last].ori.clone());
+        }
+        let i = keys.iter().rposition(|k| k.time <= time).unwrap();
+        let (k1, k2) = (&keys[i], &keys[i + 1]);
+        let span = k2.time - k1.time;
+        let t = if span > 0f32 {(time - k1.time) / span} else {0f32};
+        let p0 = keys[i.saturating_sub(1)].ori.pos;
+        let p3 = keys[(i + 2).min(last)].ori.pos;
+
+        let mut ori = k1.ori.slerp(&k2.ori, t);
+        ori.pos = catmull_rom(&p0, &k1.ori.pos, &k2.ori.pos, &p3, t);
+        ori.orthonormalize();
+        Some(ori)
+    }
+
+    /// One keyframe per line: time, then the position, right, down and forward
+    /// vectors; 13 numbers separated by spaces. Lines starting with '#' are comments.
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        let mut file = File::create(filename)?;
+        writeln!(file, "# time pos.xyz right.xyz down.xyz forward.xyz")?;
+        for k in self.keyframes.iter() {
+            let o = &k.ori;
+            writeln!(file, "{} {} {} {} {} {} {} {} {} {} {} {} {}", k.time,
+                o.pos.x, o.pos.y, o.pos.z,
+                o.right_vec.x, o.right_vec.y, o.right_vec.z,
+                o.down_vec.x, o.down_vec.y, o.down_vec.z,
+                o.forward_vec.x, o.forward_vec.y, o.forward_vec.z)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(filename: &str) -> io::Result<CameraPath> {
+        let mut text = String::new();
+        File::open(filename)?.read_to_string(&mut text)?;
+        CameraPath::from_text(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", filename, e)))
+    }
+
+    /// Parses the format written by `save`.
+    fn from_text(text: &str) -> Result<CameraPath, String> {
+        let mut path = CameraPath::new();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let nums = line.split_whitespace().map(|n| n.parse::<f32>()).collect::<Result<Vec<f32>, _>>();
+            let nums = match nums {
+                Ok(ref nums) if nums.len() == 13 && nums.iter().all(|n| n.is_finite()) => nums.clone(),
+                _ => return Err(format!("bad keyframe in line {}", line_num + 1)),
+            };
+            path.add_keyframe(nums[0], &Orientation {
+                pos: vec3::new(nums[1], nums[2], nums[3]),
+                right_vec: vec3::new(nums[4], nums[5], nums[6]),
+                down_vec: vec3::new(nums[7], nums[8], nums[9]),
+                forward_vec: vec3::new(nums[10], nums[11], nums[12]),
+            });
+        }
+        Ok(path)
+    }
+
+    /// Renders the whole path at a fixed frame rate into `dst` and writes every
+    /// frame to the file named by `filename(frame_index)` (uncompressed PNG).
+    /// `draw_extra` is called after `opticast` of every frame with the time of
+    /// the frame, to draw sprites and overlays. Returns the number of frames.
+    pub fn render_to_files<F, D>(&self, vox: &mut Voxlap, dst: &mut RenderDestination, fps: f32, focal_length: f32,
+        filename: F, mut draw_extra: D) -> u32
+        where F: Fn(u32) -> String, D: FnMut(&RenderContext, f32) {
+        assert!(fps > 0f32, "fps must be > 0");
+        if self.keyframes.is_empty() {
+            return 0;
+        }
+        let start = self.keyframes[0].time;
+        let frame_count = ((self.duration() - start) * fps).floor() as u32 + 1;
+        for frame in 0 .. frame_count {
+            let time = start + frame as f32 / fps;
+            let ori = self.sample(time).unwrap();
+            let render_context = vox.set_frame_buffer(dst);
+            render_context.set_camera(&ori, focal_length);
+            render_context.opticast();
+            draw_extra(&render_context, time);
+            render_context.save_to_file(&filename(frame));
+        }
+        frame_count
+    }
+}
+
+fn catmull_rom(p0: &vec3, p1: &vec3, p2: &vec3, p3: &vec3, t: f32) -> vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (*p1 * 2f32 + (*p2 - *p0) * t + (*p0 * 2f32 - *p1 * 5f32 + *p2 * 4f32 - *p3) * t2 + (*p1 * 3f32 - *p0 - *p2 * 3f32 + *p3) * t3) * 0.5f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ori_at(x: f32) -> Orientation {
+        Orientation::from_axes(&vec3::new(x, 0f32, 0f32),
+            &vec3::new(0f32, 1f32, 0f32), &vec3::new(0f32, 0f32, 1f32), &vec3::new(1f32, 0f32, 0f32))
+    }
+
+    #[test]
+    fn sample_empty_path() {
+        assert_eq!(CameraPath::new().sample(0f32), None);
+    }
+
+    #[test]
+    fn sample_clamps_to_the_ends() {
+        let mut path = CameraPath::new();
+        path.add_keyframe(2f32, &ori_at(20f32));
+        path.add_keyframe(1f32, &ori_at(10f32));
+        assert_eq!(path.duration(), 2f32);
+        assert_eq!(path.sample(0f32), Some(ori_at(10f32)));
+        assert_eq!(path.sample(1f32), Some(ori_at(10f32)));
+        assert_eq!(path.sample(2f32), Some(ori_at(20f32)));
+        assert_eq!(path.sample(5f32), Some(ori_at(20f32)));
+        assert_eq!(path.sample(f32::NAN), Some(ori_at(10f32)));
+    }
+
+    #[test]
+    fn record_skips_keyframes_within_the_interval() {
+        let mut path = CameraPath::new();
+        path.record(0f32, &ori_at(0f32), 0.5f32);
+        path.record(0.25f32, &ori_at(1f32), 0.5f32);
+        path.record(0.5f32, &ori_at(2f32), 0.5f32);
+        assert_eq!(path.keyframes().iter().map(|k| k.time).collect::<Vec<_>>(), vec![0f32, 0.5f32]);
+    }
+
+    #[test]
+    fn parse_empty_text_and_comments() {
+        assert_eq!(CameraPath::from_text(""), Ok(CameraPath::new()));
+        assert_eq!(CameraPath::from_text("# time pos.xyz\n\n"), Ok(CameraPath::new()));
+    }
+
+    #[test]
+    fn parse_sorts_keyframes() {
+        let path = CameraPath::from_text("2 20 0 0 0 1 0 0 0 1 1 0 0\n1 10 0 0 0 1 0 0 0 1 1 0 0\n").unwrap();
+        assert_eq!(path.keyframes()[0], Keyframe {time: 1f32, ori: ori_at(10f32)});
+        assert_eq!(path.keyframes()[1], Keyframe {time: 2f32, ori: ori_at(20f32)});
+    }
+
+    #[test]
+    fn parse_rejects_bad_lines() {
+        assert_eq!(CameraPath::from_text("# comment\n1 2 3"), Err("bad keyframe in line 2".to_string()));
+        assert!(CameraPath::from_text("1 10 0 0 0 1 0 0 0 1 1 0 x").is_err());
+        assert!(CameraPath::from_text("1 10 0 0 0 1 0 0 0 1 1 0 0 0").is_err());
+        assert!(CameraPath::from_text("NaN 10 0 0 0 1 0 0 0 1 1 0 0").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::f32::consts::PI;
+
+use bake::{self, SunSettings};
+use {ivec3, vec3, Color, Voxlap};
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct DayKeyframe {
+    /// Time of the day, 0..1: 0.25 is sunrise, 0.5 is noon, 0.75 is sunset
+    pub time: f32,
+    pub fog_color: Color,
+    /// Intensity scale of the voxels in shadow
+    pub ambient: f32,
+    /// Intensity scale of the voxels lit by the sun
+    pub sun_brightness: f32,
+    /// Sky image loaded when the keyframe is reached, None keeps the previous one
+    pub sky: Option<String>,
+}
+
+impl DayKeyframe {
+    pub fn new(time: f32, fog_color: Color, ambient: f32, sun_brightness: f32) -> DayKeyframe {
+        DayKeyframe {
+            time: time,
+            fog_color: fog_color,
+            ambient: ambient,
+            sun_brightness: sun_brightness,
+            sky: None,
+        }
+    }
+
+    pub fn sky(mut self, filename: &str) -> Self {self.sky = Some(filename.to_string()); self }
+}
+
+/// The interpolated lighting of a time of the day.
+#[derive(PartialEq, Clone, Debug)]
+pub struct DayState {
+    pub sun: SunSettings,
+    pub fog_color: Color,
+    pub sky: Option<String>,
+}
+
+/// Moves the sun over the day, interpolates the fog color and light levels
+/// between keyframes, swaps the sky and rebakes the sun shadows of a region
+/// a few rows per update, so the relighting is spread over many frames.
+pub struct DayNightCycle {
+    keyframes: Vec<DayKeyframe>,
+    /// Seconds of a whole day
+    pub day_length: f32,
+    /// Direction of the sunrise in radians, 0 is +x, PI/2 is +y
+    pub sun_azimuth: f32,
+    pub max_shadow_distance: f32,
+    /// Number of rows (y) of the region rebaked by one `update`
+    pub rows_per_update: i32,
+    time: f32,
+    region_min: ivec3,
+    region_max: ivec3,
+    next_row: i32,
+    current_sky: Option<String>,
+}
+
+impl DayNightCycle {
+    /// `region_min` and `region_max` (inclusive) bound the relit voxels.
+    pub fn new(region_min: &ivec3, region_max: &ivec3, day_length: f32) -> DayNightCycle {
+        DayNightCycle {
+            keyframes: vec![],
+            day_length: day_length,
+            sun_azimuth: 0f32,
+            max_shadow_distance: 256f32,
+            rows_per_update: 4,
+            time: 0.5f32,
+            region_min: *region_min,
+            region_max: *region_max,
+            next_row: region_min.y,
+            current_sky: None,
+        }
+    }
+
+    /// Inserts a keyframe, keeping the keyframes ordered by time.
+    pub fn add_keyframe(&mut self, keyframe: DayKeyframe) {
+        let index = self.keyframes.iter().position(|k| k.time > keyframe.time).unwrap_or(self.keyframes.len());
+        self.keyframes.insert(index, keyframe);
+    }
+
+    pub fn keyframes(&self) -> &[DayKeyframe] {
+        &self.keyframes
+    }
+
+    /// Time of the day, 0..1.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.rem_euclid(1f32);
+    }
+
+    /// Normalized direction towards the sun; its z is positive (down) at night.
+    pub fn sun_direction(&self, time: f32) -> vec3 {
+        let angle = (time - 0.25f32) * 2f32 * PI;
+        let (sin_azimuth, cos_azimuth) = self.sun_azimuth.sin_cos();
+        let horizontal = vec3::new(cos_azimuth, sin_azimuth, 0f32);
+        horizontal * angle.cos() + vec3::new(0f32, 0f32, -1f32) * angle.sin()
+    }
+
+    /// The lighting at the given time of the day. Panics without keyframes.
+    pub fn state(&self, time: f32) -> DayState {
+        assert!(!self.keyframes.is_empty(), "DayNightCycle has no keyframes");
+        let time = time.rem_euclid(1f32);
+        let keys = &self.keyframes;
+        // The keyframes wrap around midnight
+        let next = keys.iter().position(|k| k.time > time).unwrap_or(0);
+        let prev = if next == 0 {keys.len() - 1} else {next - 1};
+        let (k1, k2) = (&keys[prev], &keys[next]);
+        let span = (k2.time - k1.time).rem_euclid(1f32);
+        let t = if span > 0f32 {(time - k1.time).rem_euclid(1f32) / span} else {0f32};
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let lerp_u8 = |a: u8, b: u8| lerp(a as f32, b as f32).round() as u8;
+
+        let ambient = lerp(k1.ambient, k2.ambient);
+        let direction = self.sun_direction(time);
+        let sun_brightness = if direction.z < 0f32 {lerp(k1.sun_brightness, k2.sun_brightness)} else {ambient};
+        // The last sky at or before the time
+        let sky = (0 .. keys.len())
+            .map(|i| &keys[(prev + keys.len() - i) % keys.len()])
+            .filter_map(|k| k.sky.clone())
+            .next();
+        DayState {
+            sun: SunSettings {
+                direction: direction,
+                brightness: sun_brightness,
+                ambient: ambient,
+                max_distance: self.max_shadow_distance,
+            },
+            fog_color: Color::rgb(
+                lerp_u8(k1.fog_color.r, k2.fog_color.r),
+                lerp_u8(k1.fog_color.g, k2.fog_color.g),
+                lerp_u8(k1.fog_color.b, k2.fog_color.b)),
+            sky: sky,
+        }
+    }
+
+    /// Advances the time by `dt` seconds, applies the fog color and the sky,
+    /// then rebakes the next `rows_per_update` rows of the region.
+    pub fn update(&mut self, vox: &mut Voxlap, dt: f32) -> Result<(), String> {
+        if self.keyframes.is_empty() {
+            return Err("no keyframes".to_string());
+        }
+        if self.day_length > 0f32 {
+            let time = self.time + dt / self.day_length;
+            self.set_time(time);
+        }
+        let state = self.state(self.time);
+        vox.set_fog_color(state.fog_color);
+        if state.sky.is_some() && state.sky != self.current_sky {
+            let sky = state.sky.clone().unwrap();
+            vox.load_sky(&sky).map_err(|_| format!("could not load {}", sky))?;
+            self.current_sky = state.sky.clone();
+        }
+
+        let last_row = (self.next_row + self.rows_per_update.max(1) - 1).min(self.region_max.y);
+        let min = ivec3::new(self.region_min.x, self.next_row, self.region_min.z);
+        let max = ivec3::new(self.region_max.x, last_row, self.region_max.z);
+        bake::bake_sun_shadows(vox, &min, &max, &state.sun, |_, _| {});
+        self.next_row = if last_row >= self.region_max.y {self.region_min.y} else {last_row + 1};
+        Ok(())
+    }
+
+    /// Bakes the whole region at once with the current time, e.g. after loading the map.
+    /// Panics without keyframes.
+    pub fn rebake_all<F>(&mut self, vox: &mut Voxlap, progress: F)
+        where F: FnMut(usize, usize) {
+        let state = self.state(self.time);
+        bake::bake_sun_shadows(vox, &self.region_min, &self.region_max, &state.sun, progress);
+        self.next_row = self.region_min.y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycle() -> DayNightCycle {
+        DayNightCycle::new(&ivec3::new(0, 0, 0), &ivec3::new(0, 0, 0), 60f32)
+    }
+
+    fn assert_vec_eq(a: &vec3, b: &vec3) {
+        assert!((*a - *b).len() < 0.001, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn interpolates_across_midnight() {
+        let mut cycle = cycle();
+        cycle.add_keyframe(DayKeyframe::new(0.75f32, Color::rgb(200, 100, 50), 0.6f32, 1f32));
+        cycle.add_keyframe(DayKeyframe::new(0.25f32, Color::rgb(0, 0, 0), 0.2f32, 1f32));
+
+        // Halfway between the sunset and the sunrise keyframes
+        let state = cycle.state(0f32);
+        assert_eq!(state.fog_color, Color::rgb(100, 50, 25));
+        assert!((state.sun.ambient - 0.4f32).abs() < 0.001);
+
+        let state = cycle.state(0.9f32);
+        assert_eq!(state.fog_color, Color::rgb(140, 70, 35));
+        assert!((state.sun.ambient - 0.48f32).abs() < 0.001);
+
+        // Times out of 0..1 wrap around
+        assert_eq!(cycle.state(1f32), cycle.state(0f32));
+    }
+
+    #[test]
+    fn single_keyframe() {
+        let mut cycle = cycle();
+        cycle.add_keyframe(DayKeyframe::new(0.5f32, Color::rgb(10, 20, 30), 0.3f32, 0.9f32));
+        for &time in [0f32, 0.3f32, 0.5f32, 0.9f32].iter() {
+            let state = cycle.state(time);
+            assert_eq!(state.fog_color, Color::rgb(10, 20, 30));
+            assert_eq!(state.sun.ambient, 0.3f32);
+        }
+        // The sun only lights in the day, at night everything gets the ambient light
+        assert_eq!(cycle.state(0.5f32).sun.brightness, 0.9f32);
+        assert_eq!(cycle.state(0f32).sun.brightness, 0.3f32);
+    }
+
+    #[test]
+    fn sky_is_kept_until_the_next_sky() {
+        let mut cycle = cycle();
+        cycle.add_keyframe(DayKeyframe::new(0.25f32, Color::rgb(0, 0, 0), 0.5f32, 1f32).sky("day.png"));
+        cycle.add_keyframe(DayKeyframe::new(0.5f32, Color::rgb(0, 0, 0), 0.5f32, 1f32));
+        cycle.add_keyframe(DayKeyframe::new(0.8f32, Color::rgb(0, 0, 0), 0.5f32, 1f32).sky("night.png"));
+
+        assert_eq!(cycle.state(0.3f32).sky, Some("day.png".to_string()));
+        assert_eq!(cycle.state(0.6f32).sky, Some("day.png".to_string()));
+        assert_eq!(cycle.state(0.9f32).sky, Some("night.png".to_string()));
+        // Before the first keyframe of the day
+        assert_eq!(cycle.state(0.1f32).sky, Some("night.png".to_string()));
+
+        let mut no_sky = self::cycle();
+        no_sky.add_keyframe(DayKeyframe::new(0.5f32, Color::rgb(0, 0, 0), 0.5f32, 1f32));
+        assert_eq!(no_sky.state(0.5f32).sky, None);
+    }
+
+    #[test]
+    fn sun_direction() {
+        let mut cycle = cycle();
+        assert_vec_eq(&cycle.sun_direction(0.25f32), &vec3::new(1f32, 0f32, 0f32));
+        assert_vec_eq(&cycle.sun_direction(0.5f32), &vec3::new(0f32, 0f32, -1f32));
+        assert_vec_eq(&cycle.sun_direction(0.75f32), &vec3::new(-1f32, 0f32, 0f32));
+        assert_vec_eq(&cycle.sun_direction(0f32), &vec3::new(0f32, 0f32, 1f32));
+
+        cycle.sun_azimuth = PI / 2f32;
+        assert_vec_eq(&cycle.sun_direction(0.25f32), &vec3::new(0f32, 1f32, 0f32));
+        assert_vec_eq(&cycle.sun_direction(0.5f32), &vec3::new(0f32, 0f32, -1f32));
+    }
+}
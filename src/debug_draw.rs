@@ -0,0 +1,188 @@
+use std::f32::consts::PI;
+
+use {vec3, Color, Orientation, RenderContext, Sprite};
+
+#[derive(Clone, Debug)]
+enum Primitive {
+    Line(vec3, vec3),
+    Text(vec3, String),
+}
+
+struct Item {
+    primitive: Primitive,
+    col: Color,
+    depth_test: bool,
+    remaining: f32,
+}
+
+/// Queue of world-space debug gizmos. Every primitive stays visible for the
+/// given number of seconds (0 means only the next `draw`), call `update` after
+/// `draw` once per frame to age them.
+pub struct DebugDraw {
+    items: Vec<Item>,
+    /// Whether new primitives are hidden by the map (Z-buffered)
+    pub depth_test: bool,
+}
+
+impl DebugDraw {
+    pub fn new() -> DebugDraw {
+        DebugDraw {
+            items: vec![],
+            depth_test: true,
+        }
+    }
+
+    fn push(&mut self, primitive: Primitive, col: Color, duration: f32) {
+        self.items.push(Item {
+            primitive: primitive,
+            col: col,
+            depth_test: self.depth_test,
+            remaining: duration,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn line(&mut self, from: &vec3, to: &vec3, col: Color, duration: f32) {
+        self.push(Primitive::Line(*from, *to), col, duration);
+    }
+
+    /// Axis aligned box between two opposite corners.
+    pub fn aabb(&mut self, min: &vec3, max: &vec3, col: Color, duration: f32) {
+        let corners = [
+            vec3::new(min.x, min.y, min.z), vec3::new(max.x, min.y, min.z),
+            vec3::new(max.x, max.y, min.z), vec3::new(min.x, max.y, min.z),
+            vec3::new(min.x, min.y, max.z), vec3::new(max.x, min.y, max.z),
+            vec3::new(max.x, max.y, max.z), vec3::new(min.x, max.y, max.z)];
+        self.box_edges(&corners, col, duration);
+    }
+
+    /// Oriented box around the voxels of a KV6 sprite, following its `s`/`h`/`f` axes.
+    pub fn sprite_box(&mut self, spr: &Sprite, col: Color, duration: f32) {
+        let (size, pivot) = match spr.kv6_dimensions() {
+            Some(dims) => dims,
+            None => return,
+        };
+        let s = vec3::from_point3d(spr.ptr.s);
+        let h = vec3::from_point3d(spr.ptr.h);
+        let f = vec3::from_point3d(spr.ptr.f);
+        let pos = spr.get_pos();
+        let corner = |x: f32, y: f32, z: f32| pos + s * (x - pivot.x) + h * (y - pivot.y) + f * (z - pivot.z);
+        let (x, y, z) = (size.x as f32, size.y as f32, size.z as f32);
+        let corners = [
+            corner(0f32, 0f32, 0f32), corner(x, 0f32, 0f32), corner(x, y, 0f32), corner(0f32, y, 0f32),
+            corner(0f32, 0f32, z), corner(x, 0f32, z), corner(x, y, z), corner(0f32, y, z)];
+        self.box_edges(&corners, col, duration);
+    }
+
+    /// Corners 0-3 are the bottom face in loop order, 4-7 the top face above them.
+    fn box_edges(&mut self, corners: &[vec3; 8], col: Color, duration: f32) {
+        for i in 0 .. 4 {
+            self.line(&corners[i], &corners[(i + 1) % 4], col, duration);
+            self.line(&corners[i + 4], &corners[(i + 1) % 4 + 4], col, duration);
+            self.line(&corners[i], &corners[i + 4], col, duration);
+        }
+    }
+
+    /// Three circles around the x, y and z axes.
+    pub fn sphere(&mut self, center: &vec3, radius: f32, col: Color, duration: f32) {
+        const SEGMENTS: usize = 24;
+        let point = |axis: usize, angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            let offset = match axis {
+                0 => vec3::new(0f32, cos, sin),
+                1 => vec3::new(cos, 0f32, sin),
+                _ => vec3::new(cos, sin, 0f32),
+            };
+            *center + offset * radius
+        };
+        for axis in 0 .. 3 {
+            for i in 0 .. SEGMENTS {
+                let a0 = i as f32 / SEGMENTS as f32 * 2f32 * PI;
+                let a1 = (i + 1) as f32 / SEGMENTS as f32 * 2f32 * PI;
+                self.line(&point(axis, a0), &point(axis, a1), col, duration);
+            }
+        }
+    }
+
+    pub fn arrow(&mut self, from: &vec3, to: &vec3, col: Color, duration: f32) {
+        self.line(from, to, col, duration);
+        let dir = *to - *from;
+        let len = dir.len();
+        if len == 0f32 {
+            return;
+        }
+        let dir = dir * (1f32 / len);
+        let helper = if dir.z.abs() < 0.9f32 {vec3::new(0f32, 0f32, 1f32)} else {vec3::new(1f32, 0f32, 0f32)};
+        let side1 = dir.cross(&helper).normalize();
+        let side2 = dir.cross(&side1);
+        let head = len * 0.2f32;
+        let base = *to - dir * head;
+        for side in [side1, side1 * -1f32, side2, side2 * -1f32].iter() {
+            self.line(to, &(base + *side * (head * 0.5f32)), col, duration);
+        }
+    }
+
+    /// Right vector in red, down vector in green, forward vector in blue.
+    pub fn axes(&mut self, ori: &Orientation, size: f32, duration: f32) {
+        self.arrow(&ori.pos, &(ori.pos + ori.right_vec * size), Color::red(), duration);
+        self.arrow(&ori.pos, &(ori.pos + ori.down_vec * size), Color::green(), duration);
+        self.arrow(&ori.pos, &(ori.pos + ori.forward_vec * size), Color::blue(), duration);
+    }
+
+    /// Horizontal grid with `cells` * `cells` cells centered on `center`.
+    pub fn grid(&mut self, center: &vec3, cell_size: f32, cells: u32, col: Color, duration: f32) {
+        let (x, y, z) = (center.x, center.y, center.z);
+        let half = cells as f32 * cell_size * 0.5f32;
+        for i in 0 ..= cells {
+            let offset = i as f32 * cell_size - half;
+            self.line(&vec3::new(x - half, y + offset, z), &vec3::new(x + half, y + offset, z), col, duration);
+            self.line(&vec3::new(x + offset, y - half, z), &vec3::new(x + offset, y + half, z), col, duration);
+        }
+    }
+
+    /// Text label at a world position, drawn with the 6x8 font. Labels are
+    /// never hidden by the map.
+    pub fn text(&mut self, pos: &vec3, text: &str, col: Color, duration: f32) {
+        self.push(Primitive::Text(*pos, text.to_string()), col, duration);
+    }
+
+    /// Ages the primitives by `dt` seconds and removes the expired ones.
+    pub fn update(&mut self, dt: f32) {
+        for item in self.items.iter_mut() {
+            item.remaining -= dt;
+        }
+        self.items.retain(|item| item.remaining > 0f32);
+    }
+
+    /// Draws the queued primitives. Call it after `opticast` so the Z-buffer is filled.
+    pub fn draw(&self, render_context: &RenderContext) {
+        let (width, height) = render_context.size();
+        for item in self.items.iter() {
+            match item.primitive {
+                Primitive::Line(ref from, ref to) => if item.depth_test {
+                    render_context.draw_line_3d_with_z_buffer(from, to, item.col);
+                } else {
+                    render_context.draw_line_3d_without_z_buffer(from, to, item.col);
+                },
+                Primitive::Text(ref pos, ref text) => {
+                    let projected = render_context.project_2d(pos);
+                    let text_width = text.chars().count() as i32 * 6;
+                    let fits = projected.screen_x >= 0 && projected.screen_y >= 0 &&
+                        projected.screen_x + text_width <= width as i32 && projected.screen_y + 8 <= height as i32;
+                    if projected.visible && fits {
+                        render_context.print6x8(projected.screen_x as u32, projected.screen_y as u32, item.col, None, text);
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Default for DebugDraw {
+    fn default() -> DebugDraw {
+        DebugDraw::new()
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use {load_image, Color, Image, RenderContext};
+
+/// Position of a glyph in the font atlas and how to place it relative to the pen.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Glyph {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub x_advance: i32,
+}
+
+/// A font drawn from the glyphs of an `Image` atlas.
+pub struct BitmapFont {
+    image: Image,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), i32>,
+    line_height: i32,
+}
+
+impl BitmapFont {
+    pub fn new(image: Image, line_height: i32) -> BitmapFont {
+        BitmapFont {
+            image: image,
+            glyphs: HashMap::new(),
+            kerning: HashMap::new(),
+            line_height: line_height,
+        }
+    }
+
+    /// Monospaced font laid out as a grid of `glyph_width` * `glyph_height`
+    /// cells, row by row, starting with `first_char`.
+    pub fn from_grid(image: Image, glyph_width: u32, glyph_height: u32, first_char: char) -> BitmapFont {
+        let columns = image.width / glyph_width;
+        let rows = image.height / glyph_height;
+        let mut font = BitmapFont::new(image, glyph_height as i32);
+        for i in 0 .. columns * rows {
+            if let Some(c) = ::std::char::from_u32(first_char as u32 + i) {
+                font.add_glyph(c, Glyph {
+                    x: (i % columns) * glyph_width,
+                    y: (i / columns) * glyph_height,
+                    width: glyph_width,
+                    height: glyph_height,
+                    x_offset: 0,
+                    y_offset: 0,
+                    x_advance: glyph_width as i32,
+                });
+            }
+        }
+        font
+    }
+
+    /// Parses an AngelCode BMFont text descriptor (.fnt). Only single page fonts are supported.
+    pub fn from_bmfont(descriptor: &str, image: Image) -> Result<BitmapFont, String> {
+        let mut font = BitmapFont::new(image, 0);
+        for (line_num, line) in descriptor.lines().enumerate() {
+            let mut tokens = tokenize(line).into_iter();
+            let tag = match tokens.next() {
+                Some((tag, _)) => tag,
+                None => continue,
+            };
+            let attrs = tokens.collect::<HashMap<String, String>>();
+            let attr = |name: &str| -> Result<i32, String> {
+                attrs.get(name)
+                    .ok_or_else(|| format!("line {}: missing '{}'", line_num + 1, name))
+                    .and_then(|v| v.parse::<i32>().map_err(|_| format!("line {}: bad value for '{}': {}", line_num + 1, name, v)))
+            };
+            match tag.as_str() {
+                "common" => {
+                    font.line_height = attr("lineHeight")?;
+                    if let Some(pages) = attrs.get("pages") {
+                        if pages != "1" {
+                            return Err("multi page fonts are not supported".to_string());
+                        }
+                    }
+                },
+                "char" => {
+                    let c = ::std::char::from_u32(attr("id")? as u32)
+                        .ok_or_else(|| format!("line {}: invalid char id", line_num + 1))?;
+                    font.add_glyph(c, Glyph {
+                        x: attr("x")? as u32,
+                        y: attr("y")? as u32,
+                        width: attr("width")? as u32,
+                        height: attr("height")? as u32,
+                        x_offset: attr("xoffset")?,
+                        y_offset: attr("yoffset")?,
+                        x_advance: attr("xadvance")?,
+                    });
+                },
+                "kerning" => {
+                    let first = ::std::char::from_u32(attr("first")? as u32);
+                    let second = ::std::char::from_u32(attr("second")? as u32);
+                    if let (Some(first), Some(second)) = (first, second) {
+                        font.add_kerning(first, second, attr("amount")?);
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(font)
+    }
+
+    /// Loads a BMFont text descriptor and the atlas image it references
+    /// (relative to the descriptor).
+    pub fn load_bmfont(filename: &str) -> Result<BitmapFont, String> {
+        let mut descriptor = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut descriptor))
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        let page_file = descriptor.lines()
+            .filter_map(|line| {
+                let mut tokens = tokenize(line).into_iter();
+                match tokens.next() {
+                    Some((ref tag, _)) if tag == "page" => tokens.find(|(k, _)| k == "file").map(|(_, v)| v),
+                    _ => None,
+                }
+            })
+            .next()
+            .ok_or_else(|| format!("{}: no page file", filename))?;
+        let image_path = Path::new(filename).parent().unwrap_or_else(|| Path::new("")).join(page_file);
+        let image = load_image(&image_path.to_string_lossy());
+        if image.ptr.is_null() {
+            return Err(format!("could not load {}", image_path.display()));
+        }
+        BitmapFont::from_bmfont(&descriptor, image)
+    }
+
+    pub fn add_glyph(&mut self, c: char, glyph: Glyph) {
+        self.glyphs.insert(c, glyph);
+    }
+
+    pub fn add_kerning(&mut self, first: char, second: char, amount: i32) {
+        self.kerning.insert((first, second), amount);
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?'))
+    }
+
+    pub fn line_height(&self) -> i32 {
+        self.line_height
+    }
+
+    fn kerning(&self, prev: Option<char>, c: char) -> i32 {
+        prev.and_then(|p| self.kerning.get(&(p, c)).cloned()).unwrap_or(0)
+    }
+
+    /// Size of the text in pixels when drawn with the given scale.
+    pub fn measure(&self, text: &str, scale: f32) -> (u32, u32) {
+        let mut max_width = 0;
+        let mut line_count = 0;
+        for line in text.split('\n') {
+            line_count += 1;
+            let mut width = 0;
+            let mut prev = None;
+            for c in line.chars() {
+                if let Some(glyph) = self.glyph(c) {
+                    width += self.kerning(prev, c) + glyph.x_advance;
+                }
+                prev = Some(c);
+            }
+            max_width = max_width.max(width);
+        }
+        ((max_width as f32 * scale).ceil() as u32, (line_count as f32 * self.line_height as f32 * scale).ceil() as u32)
+    }
+
+    /// Draws UTF-8 text with its top-left corner at (x, y). Characters without
+    /// a glyph are drawn as '?' if the font has one, otherwise skipped.
+    pub fn draw(&self, render_context: &RenderContext, x: i32, y: i32, text: &str, scale: f32, col: Color) {
+        let (screen_width, screen_height) = render_context.size();
+        let mut pen_y = y as f32;
+        for line in text.split('\n') {
+            let mut pen_x = x as f32;
+            let mut prev = None;
+            for c in line.chars() {
+                let glyph = match self.glyph(c) {
+                    Some(glyph) => *glyph,
+                    None => continue,
+                };
+                pen_x += self.kerning(prev, c) as f32 * scale;
+                prev = Some(c);
+                let gx = (pen_x + glyph.x_offset as f32 * scale) as i32;
+                let gy = (pen_y + glyph.y_offset as f32 * scale) as i32;
+                let visible = glyph.width > 0 && glyph.height > 0 &&
+                    gx < screen_width as i32 && gy < screen_height as i32 &&
+                    gx + (glyph.width as f32 * scale) as i32 >= 0 && gy + (glyph.height as f32 * scale) as i32 >= 0;
+                if visible {
+                    render_context.draw_image_region(&self.image, glyph.x, glyph.y, glyph.width, glyph.height, gx, gy, scale, col);
+                }
+                pen_x += glyph.x_advance as f32 * scale;
+            }
+            pen_y += self.line_height as f32 * scale;
+        }
+    }
+}
+
+/// Splits a BMFont line into its tag and key=value pairs; values may be quoted.
+fn tokenize(line: &str) -> Vec<(String, String)> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while let Some(&c) = chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return tokens;
+        }
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '=' {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    value.push(c);
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+            }
+        }
+        tokens.push((key, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTOR: &str = "info face=\"Test Font\" size=8\n\
+        common lineHeight=10 base=8 pages=1\n\
+        page id=0 file=\"font.png\"\n\
+        char id=65 x=0 y=0 width=5 height=7 xoffset=0 yoffset=1 xadvance=6\n\
+        char id=66 x=6 y=0 width=5 height=7 xoffset=0 yoffset=1 xadvance=6\n\
+        kerning first=65 second=66 amount=-1\n";
+
+    #[test]
+    fn tokenize_quoted_values() {
+        assert_eq!(tokenize("info face=\"Test Font\" size=8"), vec![
+            ("info".to_string(), String::new()),
+            ("face".to_string(), "Test Font".to_string()),
+            ("size".to_string(), "8".to_string())]);
+        assert!(tokenize("   ").is_empty());
+    }
+
+    #[test]
+    fn bmfont_glyphs_and_kerning() {
+        let font = BitmapFont::from_bmfont(DESCRIPTOR, Image::new(16, 8)).unwrap();
+        assert_eq!(font.line_height(), 10);
+        assert_eq!(font.glyph('B').unwrap().x, 6);
+        assert_eq!(font.glyph('C'), None);
+        assert_eq!(font.measure("AB", 1f32), (11, 10));
+        assert_eq!(font.measure("BA\nA", 2f32), (24, 40));
+    }
+
+    #[test]
+    fn bmfont_empty_descriptor() {
+        let font = BitmapFont::from_bmfont("", Image::new(1, 1)).unwrap();
+        assert_eq!(font.line_height(), 0);
+        assert_eq!(font.measure("A", 1f32), (0, 0));
+    }
+
+    #[test]
+    fn bmfont_bad_lines() {
+        let missing = BitmapFont::from_bmfont("char id=65 x=0", Image::new(1, 1));
+        assert_eq!(missing.err(), Some("line 1: missing 'y'".to_string()));
+        let bad = BitmapFont::from_bmfont("common lineHeight=ten", Image::new(1, 1));
+        assert_eq!(bad.err(), Some("line 1: bad value for 'lineHeight': ten".to_string()));
+        assert!(BitmapFont::from_bmfont("common lineHeight=10 pages=2", Image::new(1, 1)).is_err());
+    }
+}
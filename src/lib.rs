@@ -7,7 +7,8 @@ pub use c_api::vspans;
 use rand::distributions::Standard;
 use RenderDestinationBuffer::Foreign;
 use RenderDestinationBuffer::Own;
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
 use std::ops::Add;
 use std::ops::Mul;
 use std::ops::Sub;
@@ -23,10 +24,38 @@ use num::range_step_inclusive;
 
 use std::mem;
 
-use libc::{c_float, c_void};
+use libc::{c_char, c_float, c_void};
 use std::ptr;
 
+pub mod bake;
 pub mod c_api;
+pub mod camera;
+pub mod camera_path;
+pub mod day_night;
+pub mod debug_draw;
+pub mod font;
+pub mod lights;
+pub mod minimap;
+pub mod particles;
+pub mod post;
+pub mod render_settings;
+pub mod sprite_batch;
+pub mod ui;
+pub mod world;
+
+pub use camera::{CameraInput, FpsCamera, FreeFlyCamera, OrbitCamera};
+pub use camera_path::CameraPath;
+pub use day_night::DayNightCycle;
+pub use debug_draw::DebugDraw;
+pub use font::BitmapFont;
+pub use lights::{LightManager, PointLight};
+pub use minimap::{Minimap, MinimapOptions};
+pub use particles::ParticleSystem;
+pub use post::PostChain;
+pub use render_settings::RenderSettings;
+pub use sprite_batch::SpriteBatch;
+pub use ui::Rect;
+pub use world::World;
 
 
 pub enum CsgOperationType {
@@ -41,6 +70,13 @@ impl CsgOperationType {
             CsgOperationType::Remove => -1,
         }
     }
+
+    fn is_insert(&self) -> bool {
+        match *self {
+            CsgOperationType::Insert => true,
+            CsgOperationType::Remove => false,
+        }
+    }
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -50,7 +86,7 @@ pub enum LightingMode {
     MultiplePointSourceLighting
 }
 
-#[derive(PartialEq, Clone, Copy, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
 #[allow(non_camel_case_types)]
 pub struct vec3 {
     pub x: f32,
@@ -95,6 +131,24 @@ impl vec3 {
         (self.x*self.x + self.y*self.y + self.z*self.z).sqrt()
     }
 
+    pub fn dot(&self, other: &vec3) -> f32 {
+        self.x*other.x + self.y*other.y + self.z*other.z
+    }
+
+    pub fn cross(&self, other: &vec3) -> vec3 {
+        vec3::new(self.y*other.z - self.z*other.y,
+            self.z*other.x - self.x*other.z,
+            self.x*other.y - self.y*other.x)
+    }
+
+    pub fn normalize(&self) -> vec3 {
+        let len = self.len();
+        if len == 0f32 {
+            return *self;
+        }
+        *self * (1f32 / len)
+    }
+
     fn from_point3d(pos: c_api::point3d) -> vec3 {
         let mut vec = vec3::null();
         vec.x = pos.x as f32;
@@ -286,6 +340,24 @@ impl Sprite {
         c_axis_rotate(&mut self.ptr.f, around_angle, w);
     }
 
+    /// Position and the `s`, `h`, `f` vectors as right, down and forward vectors.
+    pub fn get_orientation(&self) -> Orientation {
+        Orientation {
+            pos: vec3::from_point3d(self.ptr.pos),
+            right_vec: vec3::from_point3d(self.ptr.s),
+            down_vec: vec3::from_point3d(self.ptr.h),
+            forward_vec: vec3::from_point3d(self.ptr.f),
+        }
+    }
+
+    /// Sets position and rotation; the vectors are used as they are, so their length scales the sprite.
+    pub fn set_orientation(&mut self, ori: &Orientation) {
+        self.ptr.pos = *ori.pos.as_point3d();
+        self.ptr.s = *ori.right_vec.as_point3d();
+        self.ptr.h = *ori.down_vec.as_point3d();
+        self.ptr.f = *ori.forward_vec.as_point3d();
+    }
+
     pub fn scale(&mut self, scale: &vec3) {
         self.ptr.s.x *= scale.x;
         self.ptr.h.y *= scale.y;
@@ -311,6 +383,54 @@ impl Sprite {
             c_api::savekv6(filename_ptr, &*self.ptr.voxnum);
         }
     }
+
+    fn is_kv6(&self) -> bool {
+        self.ptr.flags & 2 == 0 && !self.ptr.voxnum.is_null()
+    }
+
+    /// Size of the KV6 in voxels and its pivot point, None for KFA sprites.
+    pub fn kv6_dimensions(&self) -> Option<(ivec3, vec3)> {
+        if !self.is_kv6() {
+            return None;
+        }
+        unsafe {
+            let kv6 = &*self.ptr.voxnum;
+            Some((ivec3::new(kv6.xsiz, kv6.ysiz, kv6.zsiz), vec3::new(kv6.xpiv, kv6.ypiv, kv6.zpiv)))
+        }
+    }
+
+    /// Generates all the missing lower MIP levels of the KV6. Does nothing for KFA sprites.
+    pub fn generate_mipmaps(&mut self) {
+        if !self.is_kv6() {
+            return;
+        }
+        unsafe {
+            let mut kv6: *const c_api::kv6data = self.ptr.voxnum;
+            while !kv6.is_null() {
+                kv6 = if !(*kv6).lowermip.is_null() {
+                    (*kv6).lowermip
+                } else {
+                    c_api::genmipkv6(&*kv6)
+                };
+            }
+        }
+    }
+
+    /// Number of KV6 MIP levels available, including the full detail one.
+    pub fn mip_level_count(&self) -> u32 {
+        if !self.is_kv6() {
+            return 1;
+        }
+        let mut count = 0;
+        unsafe {
+            let mut kv6: *const c_api::kv6data = self.ptr.voxnum;
+            while !kv6.is_null() {
+                count += 1;
+                kv6 = (*kv6).lowermip;
+            }
+        }
+        count
+    }
 }
 
 impl Drop for Sprite {
@@ -333,7 +453,192 @@ pub struct Orientation {
 }
 
 impl Orientation {
+    pub fn from_axes(pos: &vec3, right_vec: &vec3, down_vec: &vec3, forward_vec: &vec3) -> Orientation {
+        Orientation {
+            pos: *pos,
+            right_vec: *right_vec,
+            down_vec: *down_vec,
+            forward_vec: *forward_vec,
+        }
+    }
+
+    /// Looks from `pos` towards `target`. `up` only has to be roughly right, it
+    /// is (0, 0, -1) for the usual Voxlap camera since the z axis points down.
+    pub fn look_at(pos: &vec3, target: &vec3, up: &vec3) -> Orientation {
+        let forward = (*target - *pos).normalize();
+        let mut right = forward.cross(up);
+        if right.len() < 0.0001 {
+            // Looking straight along `up`, any right vector works
+            let helper = if forward.x.abs() < 0.9 {vec3::new(1f32, 0f32, 0f32)} else {vec3::new(0f32, 1f32, 0f32)};
+            right = forward.cross(&helper);
+        }
+        let right = right.normalize();
+        Orientation::from_axes(pos, &right, &forward.cross(&right), &forward)
+    }
+
+    /// Angles in radians. Yaw 0 looks along +x and turns towards +y, positive
+    /// pitch looks up (towards -z), roll turns around the forward vector.
+    pub fn from_yaw_pitch_roll(pos: &vec3, yaw: f32, pitch: f32, roll: f32) -> Orientation {
+        let (sin_yaw, cos_yaw) = yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = pitch.sin_cos();
+        let forward = vec3::new(cos_yaw * cos_pitch, sin_yaw * cos_pitch, -sin_pitch);
+        let right = vec3::new(-sin_yaw, cos_yaw, 0f32);
+        let mut ori = Orientation::from_axes(pos, &right, &forward.cross(&right), &forward);
+        if roll != 0f32 {
+            ori.rotate_around_forward(roll);
+        }
+        ori
+    }
+
+    /// The inverse of `from_yaw_pitch_roll`: (yaw, pitch, roll).
+    pub fn yaw_pitch_roll(&self) -> (f32, f32, f32) {
+        let f = self.forward_vec;
+        let yaw = f.y.atan2(f.x);
+        let pitch = (-f.z).clamp(-1f32, 1f32).asin();
+        let level = Orientation::from_yaw_pitch_roll(&self.pos, yaw, pitch, 0f32);
+        let roll = self.right_vec.dot(&level.down_vec).atan2(self.right_vec.dot(&level.right_vec));
+        (yaw, pitch, roll)
+    }
+
+    /// Makes the vectors perpendicular unit vectors again, keeping the direction of the right vector.
+    pub fn orthonormalize(&mut self) {
+        let mut right = self.right_vec.to_dpoint3d();
+        let mut down = self.down_vec.to_dpoint3d();
+        let mut forward = self.forward_vec.to_dpoint3d();
+        unsafe {
+            c_api::dorthonormalize(&mut right, &mut down, &mut forward);
+        }
+        self.right_vec.fill_from_dpoint3d(right);
+        self.down_vec.fill_from_dpoint3d(down);
+        self.forward_vec.fill_from_dpoint3d(forward);
+    }
 
+    /// Rotates around the orientation's own axes with dorthorotate, the way GAME.C
+    /// does mouse-look: `dorthorotate(roll, pitch, yaw)`.
+    pub fn rotate_local(&mut self, roll: f32, pitch: f32, yaw: f32) {
+        let mut right = self.right_vec.to_dpoint3d();
+        let mut down = self.down_vec.to_dpoint3d();
+        let mut forward = self.forward_vec.to_dpoint3d();
+        unsafe {
+            c_api::dorthorotate(roll as f64, pitch as f64, yaw as f64, &mut right, &mut down, &mut forward);
+        }
+        self.right_vec.fill_from_dpoint3d(right);
+        self.down_vec.fill_from_dpoint3d(down);
+        self.forward_vec.fill_from_dpoint3d(forward);
+    }
+
+    /// Rotates the vectors around a world space axis. The position does not change.
+    pub fn rotate(&mut self, axis: &vec3, w: f32) {
+        axis_rotate(&mut self.right_vec, axis, w);
+        axis_rotate(&mut self.down_vec, axis, w);
+        axis_rotate(&mut self.forward_vec, axis, w);
+    }
+
+    pub fn rotate_around_right(&mut self, w: f32) {
+        let axis = self.right_vec;
+        self.rotate(&axis, w);
+    }
+
+    pub fn rotate_around_down(&mut self, w: f32) {
+        let axis = self.down_vec;
+        self.rotate(&axis, w);
+    }
+
+    pub fn rotate_around_forward(&mut self, w: f32) {
+        let axis = self.forward_vec;
+        self.rotate(&axis, w);
+    }
+
+    /// Interpolates the rotation along the shortest path with the engine's slerp
+    /// and the position linearly. `t` = 0 gives self, 1 gives `other`.
+    pub fn slerp(&self, other: &Orientation, t: f32) -> Orientation {
+        let mut right = c_api::point3d {x: 0f32, y: 0f32, z: 0f32};
+        let mut down = right;
+        let mut forward = right;
+        unsafe {
+            c_api::slerp(self.right_vec.as_point3d(), self.down_vec.as_point3d(), self.forward_vec.as_point3d(),
+                other.right_vec.as_point3d(), other.down_vec.as_point3d(), other.forward_vec.as_point3d(),
+                &mut right, &mut down, &mut forward, t);
+        }
+        Orientation {
+            pos: self.pos + (other.pos - self.pos) * t,
+            right_vec: vec3::from_point3d(right),
+            down_vec: vec3::from_point3d(down),
+            forward_vec: vec3::from_point3d(forward),
+        }
+    }
+
+    /// World space point to local coordinates: x is right, y is down, z is forward.
+    pub fn to_local(&self, point: &vec3) -> vec3 {
+        let d = *point - self.pos;
+        vec3::new(d.dot(&self.right_vec), d.dot(&self.down_vec), d.dot(&self.forward_vec))
+    }
+
+    pub fn to_world(&self, local: &vec3) -> vec3 {
+        self.pos + self.right_vec * local.x + self.down_vec * local.y + self.forward_vec * local.z
+    }
+
+    /// Rotation matrix whose columns are the right, down and forward vectors,
+    /// so it turns local directions into world ones. Indexed as [row][column].
+    pub fn to_matrix(&self) -> [[f32; 3]; 3] {
+        let (r, d, f) = (self.right_vec, self.down_vec, self.forward_vec);
+        [[r.x, d.x, f.x],
+         [r.y, d.y, f.y],
+         [r.z, d.z, f.z]]
+    }
+
+    pub fn from_matrix(pos: &vec3, m: &[[f32; 3]; 3]) -> Orientation {
+        Orientation::from_axes(pos,
+            &vec3::new(m[0][0], m[1][0], m[2][0]),
+            &vec3::new(m[0][1], m[1][1], m[2][1]),
+            &vec3::new(m[0][2], m[1][2], m[2][2]))
+    }
+
+    /// The rotation of `to_matrix` as a unit quaternion.
+    pub fn to_quaternion(&self) -> Quaternion {
+        let m = self.to_matrix();
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        if trace > 0f32 {
+            let s = (trace + 1f32).sqrt() * 2f32;
+            Quaternion::new((m[2][1] - m[1][2]) / s, (m[0][2] - m[2][0]) / s, (m[1][0] - m[0][1]) / s, 0.25f32 * s)
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1f32 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2f32;
+            Quaternion::new(0.25f32 * s, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s, (m[2][1] - m[1][2]) / s)
+        } else if m[1][1] > m[2][2] {
+            let s = (1f32 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2f32;
+            Quaternion::new((m[0][1] + m[1][0]) / s, 0.25f32 * s, (m[1][2] + m[2][1]) / s, (m[0][2] - m[2][0]) / s)
+        } else {
+            let s = (1f32 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2f32;
+            Quaternion::new((m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, 0.25f32 * s, (m[1][0] - m[0][1]) / s)
+        }
+    }
+
+    pub fn from_quaternion(pos: &vec3, q: &Quaternion) -> Orientation {
+        let (x, y, z, w) = (q.x, q.y, q.z, q.w);
+        Orientation::from_matrix(pos, &[
+            [1f32 - 2f32*(y*y + z*z), 2f32*(x*y - z*w), 2f32*(x*z + y*w)],
+            [2f32*(x*y + z*w), 1f32 - 2f32*(x*x + z*z), 2f32*(y*z - x*w)],
+            [2f32*(x*z - y*w), 2f32*(y*z + x*w), 1f32 - 2f32*(x*x + y*y)]])
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Quaternion {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quaternion {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Quaternion {
+        Quaternion {
+            x: x,
+            y: y,
+            z: z,
+            w: w,
+        }
+    }
 }
 
 
@@ -391,9 +696,181 @@ impl Distribution<Color> for Standard {
     }
 }
 
+/// Color of a voxel in the map. Unlike `Color`, the top byte is not alpha
+/// but a brightness scale: 0x80 is neutral, 0xFF is about twice as bright.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct VoxelColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub intensity: u8,
+}
+
+impl VoxelColor {
+    pub const NEUTRAL_INTENSITY: u8 = 0x80;
+
+    /// Neutral intensity
+    pub fn rgb(r: u8, g: u8, b: u8) -> VoxelColor {
+        VoxelColor::new(r, g, b, VoxelColor::NEUTRAL_INTENSITY)
+    }
+
+    pub fn new(r: u8, g: u8, b: u8, intensity: u8) -> VoxelColor {
+        VoxelColor {
+            r: r,
+            g: g,
+            b: b,
+            intensity: intensity,
+        }
+    }
+
+    /// The color with neutral intensity, the alpha of `col` is ignored.
+    pub fn from_color(col: Color) -> VoxelColor {
+        VoxelColor::rgb(col.r, col.g, col.b)
+    }
+
+    /// The color components, opaque.
+    pub fn rgb_color(&self) -> Color {
+        Color::rgb(self.r, self.g, self.b)
+    }
+
+    /// The color as the engine shows it: the components scaled by the intensity.
+    pub fn shaded_color(&self) -> Color {
+        let channel = |c: u8| (c as u32 * self.intensity as u32 / VoxelColor::NEUTRAL_INTENSITY as u32).min(255) as u8;
+        Color::rgb(channel(self.r), channel(self.g), channel(self.b))
+    }
+
+    /// Multiplies the intensity, e.g. 0.5 for half brightness.
+    pub fn shade(&self, factor: f32) -> VoxelColor {
+        let intensity = (self.intensity as f32 * factor).round().clamp(0f32, 255f32) as u8;
+        VoxelColor {intensity: intensity, ..*self}
+    }
+
+    pub fn with_intensity(&self, intensity: u8) -> VoxelColor {
+        VoxelColor {intensity: intensity, ..*self}
+    }
+
+    /// Blends the components towards `tint` by `amount` (0..1), keeps the intensity.
+    pub fn tint(&self, tint: Color, amount: f32) -> VoxelColor {
+        let amount = amount.clamp(0f32, 1f32);
+        let channel = |c: u8, t: u8| (c as f32 + (t as f32 - c as f32) * amount).round() as u8;
+        VoxelColor::new(channel(self.r, tint.r), channel(self.g, tint.g), channel(self.b, tint.b), self.intensity)
+    }
+
+    pub fn to_i32(&self) -> i32 {
+        ((self.intensity as i32) << 24) | ((self.r as i32) << 16) | ((self.g as i32) << 8) | (self.b as i32)
+    }
+
+    pub fn from_i32(col: i32) -> VoxelColor {
+        VoxelColor::new(((col >> 16) & 0xFF) as u8, ((col >> 8) & 0xFF) as u8, (col & 0xFF) as u8, ((col >> 24) & 0xFF) as u8)
+    }
+}
+
+impl From<Color> for VoxelColor {
+    fn from(col: Color) -> VoxelColor {
+        VoxelColor::from_color(col)
+    }
+}
+
+impl From<VoxelColor> for Color {
+    fn from(col: VoxelColor) -> Color {
+        col.rgb_color()
+    }
+}
+
+impl Distribution<VoxelColor> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> VoxelColor {
+        VoxelColor::rgb(rng.gen_range(0, 255), rng.gen_range(0, 255), rng.gen_range(0, 255))
+    }
+}
+
+/// What `set_cube` writes into the map.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Voxel {
+    Air,
+    Solid(VoxelColor),
+    /// Solid, colored by the engine's color function (`vx5.colfunc`)
+    ColorFunction,
+}
+
+impl Voxel {
+    fn to_i32(&self) -> i32 {
+        match *self {
+            Voxel::Air => -1,
+            Voxel::Solid(col) => col.to_i32(),
+            Voxel::ColorFunction => -2,
+        }
+    }
+}
+
+/// Shade offset for each face of the cubes, subtracted from the voxel
+/// brightness. All zero disables side shading.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct SideShades {
+    pub z_min: i8,
+    pub z_max: i8,
+    pub x_min: i8,
+    pub x_max: i8,
+    pub y_min: i8,
+    pub y_max: i8,
+}
+
+impl SideShades {
+    pub fn new(z_min: i8, z_max: i8, x_min: i8, x_max: i8, y_min: i8, y_max: i8) -> SideShades {
+        SideShades {
+            z_min: z_min,
+            z_max: z_max,
+            x_min: x_min,
+            x_max: x_max,
+            y_min: y_min,
+            y_max: y_max,
+        }
+    }
+
+    pub fn off() -> SideShades {SideShades::new(0, 0, 0, 0, 0, 0)}
+    /// The shading of Voxed
+    pub fn editor() -> SideShades {SideShades::new(0, 28, 8, 24, 12, 12)}
+    pub fn strong() -> SideShades {SideShades::new(0, 56, 16, 48, 24, 24)}
+
+    pub fn is_off(&self) -> bool {
+        *self == SideShades::off()
+    }
+
+    pub fn get(&self, face: CubeFace) -> i8 {
+        match face {
+            CubeFace::ZMin => self.z_min,
+            CubeFace::ZMax => self.z_max,
+            CubeFace::XMin => self.x_min,
+            CubeFace::XMax => self.x_max,
+            CubeFace::YMin => self.y_min,
+            CubeFace::YMax => self.y_max,
+            CubeFace::InsideSolid => 0,
+        }
+    }
+
+    pub fn set(&mut self, face: CubeFace, shade: i8) {
+        match face {
+            CubeFace::ZMin => self.z_min = shade,
+            CubeFace::ZMax => self.z_max = shade,
+            CubeFace::XMin => self.x_min = shade,
+            CubeFace::XMax => self.x_max = shade,
+            CubeFace::YMin => self.y_min = shade,
+            CubeFace::YMax => self.y_max = shade,
+            CubeFace::InsideSolid => {},
+        }
+    }
+}
+
+impl Default for SideShades {
+    fn default() -> SideShades {
+        SideShades::off()
+    }
+}
+
 // -------------------------  Initialization functions: -------------------------
 
-pub struct Voxlap;
+pub struct Voxlap {
+    side_shades: SideShades,
+}
 
 impl Drop for Voxlap {
     fn drop(&mut self) {
@@ -407,7 +884,9 @@ impl Voxlap {
     pub fn new() -> Result<Voxlap, ()> {
         unsafe {
             match c_api::initvoxlap() {
-                0 => Ok(Voxlap),
+                0 => Ok(Voxlap {
+                    side_shades: SideShades::off(),
+                }),
                 _ => Err(())
             }
         }
@@ -451,6 +930,22 @@ impl Voxlap {
         }
     }
 
+    /// Saves the map with `ori` as the starting position of the camera.
+    pub fn save_vxl(&mut self, filename: &str, ori: &Orientation) -> Result<(), ()> {
+        let to_dpoint3d = |v: &vec3| c_api::dpoint3d { x: v.x as f64, y: v.y as f64, z: v.z as f64};
+        let mut ipo = to_dpoint3d(&ori.pos);
+        let mut ist = to_dpoint3d(&ori.right_vec);
+        let mut ihe = to_dpoint3d(&ori.down_vec);
+        let mut ifo = to_dpoint3d(&ori.forward_vec);
+        let c_str = CString::new(filename).expect("CString::new failed");
+        match unsafe {
+            c_api::savevxl(c_str.as_ptr(), &mut ipo, &mut ist, &mut ihe, &mut ifo)
+        } {
+            1 => Ok(()),
+            _ => Err(()),
+        }
+    }
+
     pub fn load_bsp(&mut self, filename: &str) -> Result<Orientation, i32> {
         let mut ipo = c_api::dpoint3d { x: 0.0, y: 0.0, z: 0.0};
         let mut ist = c_api::dpoint3d { x: 0.0, y: 0.0, z: 0.0};
@@ -482,21 +977,59 @@ impl Voxlap {
         }
     }
 
-    pub fn project_2d(&self, pos: &vec3) -> ProjecionResult {
-        let mut screen_x = 0f32;
-        let mut screen_y = 0f32;
-        let mut depth = 0f32;
-        let visible = unsafe {
-            c_api::project2d(pos.x, pos.y, pos.z, &mut screen_x, &mut screen_y, &mut depth) == 1
-        };
-        ProjecionResult {
-            screen_x: screen_x as u32,
-            screen_y: screen_y as u32,
-            depth: depth,
-            visible: visible
+    /// Reads an SXL scene: the names of its VXL and sky files (not loaded by
+    /// this call) and its sprites with their KV6/KFA files loaded.
+    /// Fails if a sprite has no file or its file can not be loaded.
+    pub fn load_sxl(&mut self, filename: &str) -> Result<SxlScene, ()> {
+        let c_str = CString::new(filename).expect("CString::new failed");
+        let mut vxl_name: *mut c_char = ptr::null_mut();
+        let mut sky_name: *mut c_char = ptr::null_mut();
+        let mut global_userst: *mut c_char = ptr::null_mut();
+        unsafe {
+            if c_api::loadsxl(c_str.as_ptr(), &mut vxl_name, &mut sky_name, &mut global_userst) == 0 {
+                return Err(());
+            }
+            let to_string = |s: *mut c_char| if s.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(s).to_string_lossy().into_owned()
+            };
+            let mut scene = SxlScene {
+                vxl_file: to_string(vxl_name),
+                sky_file: to_string(sky_name),
+                user_string: to_string(global_userst),
+                sprites: vec![],
+                sprite_user_strings: vec![],
+            };
+            // The strings point into the SXL buffer, which is walked by parspr
+            loop {
+                let mut spr = c_api::vx5sprite::new();
+                let mut userst: *mut c_char = ptr::null_mut();
+                let kv6_name = c_api::parspr(&mut spr, &mut userst);
+                if kv6_name.is_null() {
+                    break;
+                }
+                if *kv6_name == 0 {
+                    return Err(());
+                }
+                c_api::getspr(&mut spr, kv6_name);
+                if spr.voxnum.is_null() {
+                    return Err(());
+                }
+                scene.sprites.push(Sprite {
+                    ptr: spr,
+                    managed_by_voxlap: true,
+                });
+                scene.sprite_user_strings.push(to_string(userst));
+            }
+            Ok(scene)
         }
     }
 
+    pub fn project_2d(&self, pos: &vec3) -> ProjecionResult {
+        project_2d(pos)
+    }
+
     pub fn melt_sphere(&self, center: &ivec3, radius: u32) -> (Sprite, u32) {
         let mut spr = c_api::vx5sprite::new();
         let melted_voxel_count = unsafe {
@@ -524,35 +1057,14 @@ impl Voxlap {
             c_api::voxsetframebuffer(render_dst.as_mut_ptr(), render_dst.bytes_per_line, render_dst.width, render_dst.height);
             RenderContext {
                 render_dst: render_dst,
+                camera: RefCell::new(None),
             }
         }
     }
 
 
     pub fn hitscan(&mut self, pos: &vec3, dir: &vec3) -> Option<HitScanResult> {
-        let mut voxel_pos = ivec3::new(0, 0, 0);
-        let mut face: i32 = 0;
-        unsafe {
-            let mut color_ptr: *mut i32 = ptr::null_mut();
-            c_api::hitscan(&pos.to_dpoint3d(), &dir.to_dpoint3d(), voxel_pos.as_mut_lpoint3d(), &mut color_ptr, &mut face);
-            if color_ptr == ptr::null_mut() {
-                None
-            } else {
-                Some(HitScanResult {
-                    color_ptr: color_ptr,
-                    hit_face: match face {
-                        0 => Some(CubeFace::ZMin),
-                        1 => Some(CubeFace::ZMax),
-                        2 => Some(CubeFace::XMin),
-                        3 => Some(CubeFace::XMax),
-                        4 => Some(CubeFace::YMin),
-                        5 => Some(CubeFace::YMax),
-                        _ => None,  // -1 if inside solid
-                    },
-                    pos: voxel_pos,
-                })
-            }
-        }
+        hitscan(pos, dir)
     }
 
     pub fn with_hitscan<F>(&mut self, pos: &vec3, dir: &vec3, mut func: F) -> bool  
@@ -584,20 +1096,7 @@ impl Voxlap {
     }
 
     pub fn sprhitscan(&self, pos: &vec3, dir: &vec3, spr: &Sprite, ) -> Option<SprHitScanResult> {
-        let mut voxel_pos = ivec3::new(0, 0, 0);
-        let mut unk = 100f32;
-        unsafe {
-            let mut kv6voxtype_ptr: *mut c_api::kv6voxtype = ptr::null_mut();
-            c_api::sprhitscan(&pos.to_dpoint3d(), &dir.to_dpoint3d(), &spr.ptr, voxel_pos.as_mut_lpoint3d(), &mut kv6voxtype_ptr, &mut unk);
-            if kv6voxtype_ptr == ptr::null_mut() {
-                None
-            } else {
-                Some(SprHitScanResult {
-                    //color: Color::from_i32(*color_ptr),
-                    pos: voxel_pos
-                })
-            }
-        }
+        sprhitscan(pos, dir, spr, 100f32)
     }
 
     pub fn calc_air_radius(&self, pos: &vec3, maxcr: f32) -> f32 {
@@ -664,22 +1163,22 @@ impl Voxlap {
         }
     }
 
-    pub fn get_cube(&self, x: i32, y: i32, z: i32, ) -> Option<Color> {
+    /// Returns None for air and for solid voxels which are not exposed (they have no color).
+    pub fn get_cube(&self, x: i32, y: i32, z: i32, ) -> Option<VoxelColor> {
         unsafe {
             let ptr_to_color = c_api::getcube(x, y, z) as *const i32;
             if ptr_to_color == ptr::null() || (ptr_to_color as i32) == 1 {
                 return None;
             }
-            return Some(Color::from_i32(*ptr_to_color));
+            return Some(VoxelColor::from_i32(*ptr_to_color));
         }
     }
 
     // --------------------------- VXL writing functions: ---------------------------
 
-    pub fn set_cube(&mut self, pos: &ivec3, col: Option<Color>) {
+    pub fn set_cube(&mut self, pos: &ivec3, voxel: Voxel) {
         unsafe {
-            let col = col.map_or(-1, |c| c.to_i32());
-            c_api::setcube(pos.x, pos.y, pos.z, col);
+            c_api::setcube(pos.x, pos.y, pos.z, voxel.to_i32());
         }
     }
 
@@ -751,17 +1250,84 @@ impl Voxlap {
         }
     }
 
-    // sethull3d
-    // setlathe
-    // setblobs
-    // setfloodfill3d
-    // sethollowfill
+    /// Inserts or removes the convex hull of the points. The engine locks up
+    /// if faces of the hull are coplanar (e.g. the 8 corners of a box) or the
+    /// hull touches the edge of the map, so such input is rejected.
+    pub fn set_hull_3d(&mut self, points: &[vec3], mode: CsgOperationType) -> Result<(), String> {
+        validate_hull(points, self.get_max_xy_dimension())?;
+        let points = points.iter().map(|p| *p.as_point3d()).collect::<Vec<c_api::point3d>>();
+        unsafe {
+            // 0: fast&permanent change, 1:backup (much slower: used in VOXED)
+            c_api::sethull3d(&points[0], points.len() as i32, mode.as_int(), 0);
+        }
+        Ok(())
+    }
+
+    /// Rotational sweep of a polygon. The first 2 vertices define the axis of
+    /// the rotation, all vertices have to be on the same plane.
+    pub fn set_lathe(&mut self, vertices: &[vec3], mode: CsgOperationType) -> Result<(), String> {
+        validate_lathe(vertices)?;
+        let points = vertices.iter().map(|p| *p.as_point3d()).collect::<Vec<c_api::point3d>>();
+        unsafe {
+            c_api::setlathe(&points[0], points.len() as i32, mode.as_int(), 0);
+        }
+        Ok(())
+    }
+
+    /// Metaballs around the sources. `threshold` is the cutoff value of the
+    /// field (`vx5.currad`), smaller values give bigger blobs.
+    pub fn set_blobs(&mut self, sources: &[vec3], threshold: i32, mode: CsgOperationType) -> Result<(), String> {
+        if sources.is_empty() || sources.len() > MAX_CURS {
+            return Err(format!("blobs need 1..{} sources, got {}", MAX_CURS, sources.len()));
+        }
+        check_finite(sources)?;
+        let points = sources.iter().map(|p| *p.as_point3d()).collect::<Vec<c_api::point3d>>();
+        unsafe {
+            let old_currad = c_api::get_currad();
+            c_api::set_currad(threshold);
+            c_api::setblobs(&points[0], points.len() as i32, mode.as_int(), 0);
+            c_api::set_currad(old_currad);
+        }
+        Ok(())
+    }
+
+    /// Fills the air connected to `start` with solid voxels, without leaving
+    /// the box between `min` (inclusive) and `max` (exclusive).
+    pub fn set_flood_fill_3d(&mut self, start: &ivec3, min: &ivec3, max: &ivec3) {
+        let size = self.get_max_xy_dimension();
+        let min = ivec3::new(min.x.max(0), min.y.max(0), min.z.max(0));
+        let max = ivec3::new(max.x.min(size), max.y.min(size), max.z.min(256));
+        let inside = start.x >= min.x && start.y >= min.y && start.z >= min.z &&
+            start.x < max.x && start.y < max.y && start.z < max.z;
+        if !inside {
+            return;
+        }
+        unsafe {
+            c_api::setfloodfill3d(start.x, start.y, start.z, min.x, min.y, min.z, max.x, max.y, max.z);
+        }
+    }
+
+    /// Fills every air area which is not connected to the sky. Very slow.
+    pub fn set_hollow_fill(&mut self, ) {
+        unsafe {
+            c_api::sethollowfill();
+        }
+    }
+
     pub fn set_norm_flash(&mut self, pos: &vec3, flash_radius: i32, intens: i32) {
         unsafe {
             c_api::setnormflash(pos.x, pos.y, pos.z, flash_radius, intens);
         }
     }
 
+    /// Old lighting function, it has aliasing artifacts. `num_angles` is the
+    /// angle density (512, 1024 or 2048 are recommended).
+    pub fn set_flash(&mut self, pos: &vec3, flash_radius: i32, num_angles: i32, intens: i32) {
+        unsafe {
+            c_api::setflash(pos.x, pos.y, pos.z, flash_radius, num_angles, intens);
+        }
+    }
+
 
     // ---------------------------- VXL MISC functions:  ----------------------------
     // updatebbox
@@ -804,6 +1370,66 @@ impl Voxlap {
         }
     }
 
+    pub fn get_max_scan_dist(&self, ) -> i32 {
+        unsafe {
+            c_api::get_maxscandist()
+        }
+    }
+
+    /// Distance where the VXL mip-mapping starts.
+    pub fn set_mip_scan_dist(&mut self, dist: i32) {
+        unsafe {
+            c_api::set_mipscandist(dist);
+        }
+    }
+
+    pub fn get_mip_scan_dist(&self, ) -> i32 {
+        unsafe {
+            c_api::get_mipscandist()
+        }
+    }
+
+    /// Number of VXL mip levels used by the renderer, 1 disables mip-mapping.
+    pub fn set_vxl_mip_use(&mut self, levels: i32) {
+        assert!(levels >= 1, "levels cannot be < 1!");
+        unsafe {
+            c_api::set_vxlmipuse(levels);
+        }
+    }
+
+    pub fn get_vxl_mip_use(&self, ) -> i32 {
+        unsafe {
+            c_api::get_vxlmipuse()
+        }
+    }
+
+    /// Higher values keep the full detail KV6 sprites for longer distances.
+    pub fn set_kv6_mip_factor(&mut self, factor: i32) {
+        unsafe {
+            c_api::set_kv6mipfactor(factor);
+        }
+    }
+
+    pub fn get_kv6_mip_factor(&self, ) -> i32 {
+        unsafe {
+            c_api::get_kv6mipfactor()
+        }
+    }
+
+    /// `draw_sprite` renders only the KV6 x-planes in `min .. max`.
+    pub fn set_xplane_range(&mut self, min: i32, max: i32) {
+        unsafe {
+            c_api::set_xplanemin(min);
+            c_api::set_xplanemax(max);
+        }
+    }
+
+    pub fn get_xplane_range(&self, ) -> (i32, i32) {
+        unsafe {
+            (c_api::get_xplanemin(), c_api::get_xplanemax())
+        }
+    }
+
 
     pub fn set_lighting_mode(&mut self, mode: LightingMode) {
         let m = match mode {
@@ -817,6 +1443,14 @@ impl Voxlap {
 
     }
 
+    pub fn get_lighting_mode(&self, ) -> LightingMode {
+        match unsafe { c_api::get_lightmode() } {
+            1 => LightingMode::SimpleEstimatedNormalLighting,
+            2 => LightingMode::MultiplePointSourceLighting,
+            _ => LightingMode::NoSpecialLighting,
+        }
+    }
+
     pub fn set_raycast_density(&mut self, param: i32) {
         assert!(param >= 1, "Param cannot be < 0!");
         unsafe {
@@ -831,8 +1465,25 @@ impl Voxlap {
     }
 
     pub fn set_fog_color(&mut self, param: Color) {
+        // A negative fogcol disables the fog, so the alpha byte must be cleared
         unsafe {
-            c_api::set_fogcol(param.to_i32());
+            c_api::set_fogcol(param.to_i32() & 0x00FFFFFF);
+        }
+    }
+
+    pub fn disable_fog(&mut self, ) {
+        unsafe {
+            c_api::set_fogcol(-1);
+        }
+    }
+
+    /// None if the fog is disabled.
+    pub fn get_fog_color(&self, ) -> Option<Color> {
+        let fogcol = unsafe { c_api::get_fogcol() };
+        if fogcol < 0 {
+            None
+        } else {
+            Some(Color::from_i32(fogcol | 0xFF000000u32 as i32))
         }
     }
 
@@ -842,12 +1493,33 @@ impl Voxlap {
         }
     }
 
-    pub fn set_curcol(&mut self, param: Color) {
+    pub fn get_kv6col(&self, ) -> Color {
+        unsafe {
+            Color::from_i32(c_api::get_kv6col() | 0xFF000000u32 as i32)
+        }
+    }
+
+    /// Color of the voxels exposed or created by the CSG functions.
+    pub fn set_curcol(&mut self, param: VoxelColor) {
         unsafe {
             c_api::set_curcol(param.to_i32());
         }
     }
 
+    pub fn set_side_shades(&mut self, shades: SideShades) {
+        unsafe {
+            c_api::setsideshades(shades.z_min as c_char, shades.z_max as c_char,
+                shades.x_min as c_char, shades.x_max as c_char,
+                shades.y_min as c_char, shades.y_max as c_char);
+        }
+        self.side_shades = shades;
+    }
+
+    /// The shades set by the last `set_side_shades` call, so they can be restored later.
+    pub fn get_side_shades(&self) -> SideShades {
+        self.side_shades
+    }
+
     pub fn set_curpow(&self, param: c_float) {
         unsafe {
             c_api::set_curpow(param);
@@ -876,6 +1548,12 @@ impl Voxlap {
         return self.meltspans(spans.as_slice(), pos);
     }
 
+    /// Top-down color image of the map area, one pixel per `scale` * `scale`
+    /// columns. See `minimap::Minimap` for shading options and incremental updates.
+    pub fn render_minimap(&self, region: &Rect, scale: u32) -> Image {
+        minimap::render(self, region, scale, &MinimapOptions::default())
+    }
+
     pub fn can_see(&self, starting_point: &vec3, ending_point: &vec3) -> VisibilityResult {
         let mut hit_pos = ivec3::new(0, 0, 0);
         match unsafe {
@@ -892,7 +1570,7 @@ impl Voxlap {
 pub fn load_image(filename: &str) -> Image {
     let c_str = CString::new(filename).expect("CString::new failed");
     let filename_ptr = c_str.as_ptr();
-    let mut ptr: i32 = 0;
+    let mut ptr: *mut u8 = ptr::null_mut();
     let mut bpl: u32 = 0;
     let mut xsiz: u32 = 0;
     let mut ysiz: u32 = 0;
@@ -904,7 +1582,8 @@ pub fn load_image(filename: &str) -> Image {
         width: xsiz,
         height: ysiz,
         bytes_per_line: bpl,
-        ptr: ptr as *mut u8,
+        ptr: ptr,
+        own_buffer: None,
     }
 }
 
@@ -935,7 +1614,57 @@ pub struct RenderDestination {
 }
 
 pub struct RenderContext<'a> {
-    render_dst: &'a mut RenderDestination
+    render_dst: &'a mut RenderDestination,
+    camera: RefCell<Option<CameraSetting>>,
+}
+
+struct CameraSetting {
+    ori: Orientation,
+    focal_length: f32,
+    center_x: f32,
+    center_y: f32,
+}
+
+/// A part of the render destination with its own camera, for split-screen,
+/// mirrors or picture-in-picture views.
+#[derive(Clone, Debug)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub camera: Orientation,
+    pub focal_length: f32,
+    /// Projection center relative to the viewport, None for its middle.
+    pub center: Option<(f32, f32)>,
+}
+
+impl Viewport {
+    pub fn new(x: u32, y: u32, width: u32, height: u32, camera: &Orientation, focal_length: f32) -> Viewport {
+        Viewport {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+            camera: camera.clone(),
+            focal_length: focal_length,
+            center: None,
+        }
+    }
+
+    /// Screen areas (x, y, width, height) for split-screen with 1-4 players:
+    /// 2 players are split horizontally, 3 and 4 get the quarters of the screen.
+    pub fn split_screen(width: u32, height: u32, players: u32) -> Vec<(u32, u32, u32, u32)> {
+        let half_w = width / 2;
+        let half_h = height / 2;
+        match players {
+            0 | 1 => vec![(0, 0, width, height)],
+            2 => vec![(0, 0, width, half_h), (0, half_h, width, height - half_h)],
+            3 => vec![(0, 0, width, half_h), (0, half_h, half_w, height - half_h), (half_w, half_h, width - half_w, height - half_h)],
+            _ => vec![(0, 0, half_w, half_h), (half_w, 0, width - half_w, half_h),
+                      (0, half_h, half_w, height - half_h), (half_w, half_h, width - half_w, height - half_h)],
+        }
+    }
 }
 
 pub struct Image {
@@ -943,17 +1672,40 @@ pub struct Image {
     pub height: u32,
     pub bytes_per_line: u32,
     ptr: *mut u8,
+    // Some if the pixels were allocated by Rust, None if by Voxlap
+    own_buffer: Option<Vec<i32>>,
 }
 
 impl Drop for Image {
     fn drop(&mut self) {
-        unsafe {
-            c_api::vox_free(self.ptr as *const c_void);
+        if self.own_buffer.is_none() {
+            unsafe {
+                c_api::vox_free(self.ptr as *const c_void);
+            }
         }
     }
 }
 
 impl Image {
+    /// A black, fully transparent image.
+    pub fn new(width: u32, height: u32) -> Image {
+        let mut buffer = vec![0i32; (width * height) as usize];
+        Image {
+            width: width,
+            height: height,
+            bytes_per_line: width * 4,
+            ptr: buffer.as_mut_ptr() as *mut u8,
+            own_buffer: Some(buffer),
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, col: Color) {
+        assert!(x < self.width && y < self.height, "({}, {}) is outside of the image", x, y);
+        unsafe {
+            let row = self.ptr.offset((y * self.bytes_per_line) as isize) as *mut i32;
+            *row.offset(x as isize) = col.to_i32();
+        }
+    }
 
     pub fn get_pixel(&self, x: u32, y: u32) -> Color {
         let elem_count = (self.width * self.height) as u32; 
@@ -1002,7 +1754,7 @@ impl RenderDestination {
     }
 
     fn in_screen_x(&self, num: u32) -> bool {
-        num < self.width && num < self.height
+        num < self.width
     }
 
     fn in_screen_y(&self, num: u32) -> bool {
@@ -1028,7 +1780,7 @@ impl RenderDestination {
         unsafe {
             let ptr = buff.as_ptr() as *mut u8;
             RenderDestination {
-                buffer: Foreign(CVec::new(ptr as *mut Color, (buffer_width * buffer_height * 4) as usize)),
+                buffer: Foreign(CVec::new(ptr as *mut Color, buff.len() / 4)),
                 width: buffer_width,
                 height: buffer_height,
                 bytes_per_line: bytes_per_line,
@@ -1039,8 +1791,14 @@ impl RenderDestination {
     pub fn width(&self) -> u32 {self.width}
     pub fn height(&self) -> u32 {self.height}
 
+    /// Rows are `bytes_per_line` apart, which is more than the width for a
+    /// viewport or a padded foreign buffer.
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * (self.bytes_per_line / 4) + x) as usize
+    }
+
     pub fn get(&self, x: u32, y: u32) -> Color {
-        let index = (y * self.width + x) as usize;
+        let index = self.index(x, y);
         match self.buffer {
             Foreign(ref buffer) => {
                 *buffer.get(index).unwrap()
@@ -1052,10 +1810,10 @@ impl RenderDestination {
     }
 
     pub fn set(&mut self, x: u32, y: u32, col: Color) {
-        let index = (y * self.width + x) as usize;
+        let index = self.index(x, y);
         match self.buffer {
-            Foreign(_) => {
-                panic!("Cannot set color for a Foreign (allocated by Voxlap) buffer");
+            Foreign(ref mut buffer) => {
+                *buffer.get_mut(index).unwrap() = col;
             },
             Own(ref mut buffer) => {
                 (buffer.as_mut_slice())[index] = col;
@@ -1068,16 +1826,132 @@ impl RenderDestination {
 impl<'a> RenderContext<'a> {
 
     pub fn set_camera(&self, ori: &Orientation, focal_length: f32) {
+        let center_x = self.render_dst.width as f32 * 0.5f32;
+        let center_y = self.render_dst.height as f32 * 0.5f32;
+        self.set_camera_with_center(ori, focal_length, center_x, center_y);
+    }
+
+    /// Like `set_camera`, but the projection center (the screen point the
+    /// forward vector goes through) can be anywhere, not only in the middle.
+    pub fn set_camera_with_center(&self, ori: &Orientation, focal_length: f32, center_x: f32, center_y: f32) {
         let ref dst = self.render_dst;
         unsafe {
             c_api::setcamera(&ori.pos.to_dpoint3d(),
                 &ori.right_vec.to_dpoint3d(),
                 &ori.down_vec.to_dpoint3d(),
                 &ori.forward_vec.to_dpoint3d(),
-                dst.width as f32* 0.5f32, dst.height as f32*0.5f32, dst.width as f32 * 0.5f32 * focal_length);
+                center_x, center_y, dst.width as f32 * 0.5f32 * focal_length);
+        }
+        *self.camera.borrow_mut() = Some(CameraSetting {
+            ori: ori.clone(),
+            focal_length: focal_length,
+            center_x: center_x,
+            center_y: center_y,
+        });
+    }
+
+    /// The orientation, focal length and projection center (x, y) of the last
+    /// `set_camera` or `set_camera_with_center` call.
+    pub fn current_camera(&self) -> Option<(Orientation, f32, (f32, f32))> {
+        self.camera.borrow().as_ref().map(|c| (c.ori.clone(), c.focal_length, (c.center_x, c.center_y)))
+    }
+
+    /// Renders into the given part of the destination buffer with its own
+    /// camera, then calls `draw` to draw sprites/overlays into the viewport.
+    /// The frame buffer and camera of this context are restored afterwards.
+    pub fn render_viewport<F>(&mut self, viewport: &Viewport, draw: F)
+        where F: FnOnce(&RenderContext) {
+        assert!(viewport.width > 0 && viewport.height > 0, "empty viewport");
+        assert!(viewport.x + viewport.width <= self.render_dst.width && viewport.y + viewport.height <= self.render_dst.height,
+            "the viewport is outside of the render destination");
+        let bytes_per_line = self.render_dst.bytes_per_line;
+        let mut sub_dst = unsafe {
+            let ptr = self.render_dst.as_mut_ptr().offset((viewport.y * bytes_per_line + viewport.x * 4) as isize);
+            RenderDestination {
+                buffer: Foreign(CVec::new(ptr as *mut Color, ((viewport.height - 1) * bytes_per_line / 4 + viewport.width) as usize)),
+                width: viewport.width,
+                height: viewport.height,
+                bytes_per_line: bytes_per_line,
+            }
+        };
+        unsafe {
+            c_api::voxsetframebuffer(sub_dst.as_mut_ptr(), bytes_per_line, viewport.width, viewport.height);
+        }
+        {
+            let sub_context = RenderContext {
+                render_dst: &mut sub_dst,
+                camera: RefCell::new(None),
+            };
+            let (center_x, center_y) = viewport.center.unwrap_or((viewport.width as f32 * 0.5f32, viewport.height as f32 * 0.5f32));
+            sub_context.set_camera_with_center(&viewport.camera, viewport.focal_length, center_x, center_y);
+            sub_context.opticast();
+            draw(&sub_context);
+        }
+
+        unsafe {
+            let dst = &mut *self.render_dst;
+            c_api::voxsetframebuffer(dst.as_mut_ptr(), dst.bytes_per_line, dst.width, dst.height);
+        }
+        let previous = self.camera.borrow_mut().take();
+        if let Some(c) = previous {
+            self.set_camera_with_center(&c.ori, c.focal_length, c.center_x, c.center_y);
+        }
+    }
+
+    /// Renders every viewport with `render_viewport`. `draw` gets the index of the viewport.
+    pub fn render_viewports<F>(&mut self, viewports: &[Viewport], mut draw: F)
+        where F: FnMut(&RenderContext, usize) {
+        for (i, viewport) in viewports.iter().enumerate() {
+            self.render_viewport(viewport, |render_context| draw(render_context, i));
         }
     }
 
+    /// Width and height of the destination buffer.
+    pub fn size(&self) -> (u32, u32) {
+        (self.render_dst.width, self.render_dst.height)
+    }
+
+    /// Projects with the camera of the last `set_camera` call.
+    pub fn project_2d(&self, pos: &vec3) -> ProjecionResult {
+        project_2d(pos)
+    }
+
+    /// World-space ray through the center of the given pixel: the camera
+    /// position and a normalized direction. Panics if no camera was set.
+    pub fn screen_ray(&self, x: f32, y: f32) -> (vec3, vec3) {
+        let camera = self.camera.borrow();
+        let camera = camera.as_ref().expect("screen_ray called before set_camera");
+        let focal_pixels = self.render_dst.width as f32 * 0.5f32 * camera.focal_length;
+        let ori = &camera.ori;
+        let dir = ori.forward_vec * focal_pixels
+            + ori.right_vec * (x + 0.5f32 - camera.center_x)
+            + ori.down_vec * (y + 0.5f32 - camera.center_y);
+        (ori.pos, dir.normalize())
+    }
+
+    /// The first voxel of the map under the pixel. Edit it through `Voxlap`,
+    /// e.g. with `set_cube`.
+    pub fn pick_voxel(&self, x: f32, y: f32) -> Option<VoxelPickResult> {
+        let (pos, dir) = self.screen_ray(x, y);
+        hitscan(&pos, &dir).map(|hit| VoxelPickResult {
+            hit_face: hit.hit_face,
+            pos: hit.pos,
+            color: hit.get_color(),
+        })
+    }
+
+    /// The nearest of the sprites under the pixel with its index, or None if
+    /// the ray does not hit any of them or the map is in front of them.
+    pub fn pick_sprite(&self, x: f32, y: f32, sprites: &[&Sprite]) -> Option<(usize, SprHitScanResult)> {
+        const MAX_DISTANCE: f32 = 2048f32;
+        let (pos, dir) = self.screen_ray(x, y);
+        let map_distance = hitscan(&pos, &dir).map_or(f32::INFINITY, |hit| (hit.pos.to_vec3() - pos).len());
+        sprites.iter().enumerate()
+            .filter_map(|(i, spr)| sprhitscan(&pos, &dir, spr, MAX_DISTANCE).map(|hit| (i, hit)))
+            .filter(|(_, hit)| hit.distance <= map_distance)
+            .min_by(|(_, a), (_, b)| a.distance.partial_cmp(&b.distance).unwrap())
+    }
+
     pub fn opticast(&self) {
         unsafe {
             c_api::opticast();
@@ -1098,13 +1972,39 @@ impl<'a> RenderContext<'a> {
         }
     }
 
+    /// The parts of the line outside of the screen are not drawn.
     pub fn draw_line_2d(&self, x1: u32, y1: u32, x2: u32, y2: u32, col: Color) {
-        assert!(self.render_dst.in_screen_x(x1), "x1 = {}", x1);
-        assert!(self.render_dst.in_screen_x(x2), "x2 = {}", x2);
-        assert!(self.render_dst.in_screen_y(y1), "y1 = {}", y1);
-        assert!(self.render_dst.in_screen_y(y2), "y2 = {}", y2);
+        self.draw_line_2d_clipped(x1 as f32, y1 as f32, x2 as f32, y2 as f32, col);
+    }
+
+    /// Like `draw_line_2d`, but accepts coordinates outside of the screen (even negative ones).
+    pub fn draw_line_2d_clipped(&self, x1: f32, y1: f32, x2: f32, y2: f32, col: Color) {
+        let max_x = self.render_dst.width as f32 - 1f32;
+        let max_y = self.render_dst.height as f32 - 1f32;
+        // Liang-Barsky clipping against the screen rectangle
+        let dx = x2 - x1;
+        let dy = y2 - y1;
+        let mut t0 = 0f32;
+        let mut t1 = 1f32;
+        for &(p, q) in [(-dx, x1), (dx, max_x - x1), (-dy, y1), (dy, max_y - y1)].iter() {
+            if p == 0f32 {
+                if q < 0f32 {
+                    return;
+                }
+            } else {
+                let t = q / p;
+                if p < 0f32 {
+                    t0 = t0.max(t);
+                } else {
+                    t1 = t1.min(t);
+                }
+            }
+        }
+        if t0 > t1 {
+            return;
+        }
         unsafe {
-            c_api::drawline2d(x1 as f32, y1 as f32, x2 as f32, y2 as f32, col.to_i32());
+            c_api::drawline2d(x1 + t0 * dx, y1 + t0 * dy, x1 + t1 * dx, y1 + t1 * dy, col.to_i32());
         }
     }
 
@@ -1134,6 +2034,34 @@ impl<'a> RenderContext<'a> {
         }
     }
 
+    /// The Z-buffer of the last `opticast` (and the Z-buffered drawing after it).
+    /// A value is the depth of the pixel along the forward vector of the
+    /// camera.
+    ///
+    /// The buffer is strided: the depth of (x, y) is at `y * depth_stride() + x`,
+    /// not at `y * width + x`. The stride is the pitch of the destination in
+    /// floats, which is larger than the width when the destination has padded
+    /// rows. The padding after the last row is not part of the slice.
+    /// `depth_at` does the indexing with bounds checks.
+    pub fn depth_buffer(&self) -> &[f32] {
+        let ref dst = self.render_dst;
+        let len = ((dst.height - 1) * dst.bytes_per_line / 4 + dst.width) as usize;
+        unsafe {
+            let ptr = dst.as_ptr().offset(c_api::get_zbufoff() as isize) as *const f32;
+            std::slice::from_raw_parts(ptr, len)
+        }
+    }
+
+    /// The distance between the rows of `depth_buffer()`, in floats.
+    pub fn depth_stride(&self) -> u32 {
+        self.render_dst.bytes_per_line / 4
+    }
+
+    pub fn depth_at(&self, x: u32, y: u32) -> f32 {
+        assert!(x < self.render_dst.width && y < self.render_dst.height, "({}, {}) is outside of the screen", x, y);
+        self.depth_buffer()[(y * self.depth_stride() + x) as usize]
+    }
+
     pub fn draw_image_2d(&mut self, img: &Image, x: u32, y: u32, w: u32, h: u32) {
         let ref mut dst = self.render_dst;
         unsafe {
@@ -1147,22 +2075,76 @@ impl<'a> RenderContext<'a> {
         }
     }
 
-    pub fn draw_image_3d(img: &Image, pos0: &vec3, pos1: &vec3, pos2: &vec3, pos3: &vec3) {
+    pub fn draw_image_3d(&self, img: &Image, pos0: &vec3, pos1: &vec3, pos2: &vec3, pos3: &vec3) {
+        self.draw_textured_quad(img, [*pos0, *pos1, *pos2, *pos3],
+            [(0f32, 0f32), (0f32, img.height as f32), (img.width as f32, img.height as f32)]);
+    }
+
+    /// Corners are in loop order, UVs are texel coordinates of the first 3 corners
+    /// (the 4th one is derived by the engine). Nothing is drawn if a UV,
+    /// including the derived one, is outside of the image.
+    pub fn draw_textured_quad(&self, img: &Image, corners: [vec3; 4], uvs: [(f32, f32); 3]) {
+        let uv3 = (uvs[0].0 + uvs[2].0 - uvs[1].0, uvs[0].1 + uvs[2].1 - uvs[1].1);
+        let in_image = |&(u, v): &(f32, f32)|
+            u >= 0f32 && v >= 0f32 && u <= img.width as f32 && v <= img.height as f32;
+        if !uvs.iter().chain(Some(&uv3)).all(in_image) {
+            return;
+        }
         unsafe {
-            c_api::drawpolyquad(img.ptr as i32, img.bytes_per_line, img.width, img.height,
-                pos0.x, pos0.y, pos0.z, 0f32, 0f32,
-                pos1.x, pos1.y, pos1.z, 0f32, img.height as f32,
-                pos2.x, pos2.y, pos2.z, img.width as f32, img.height as f32,
-                pos3.x, pos3.y, pos3.z);
+            c_api::drawpolyquad(img.ptr, img.bytes_per_line, img.width, img.height,
+                corners[0].x, corners[0].y, corners[0].z, uvs[0].0, uvs[0].1,
+                corners[1].x, corners[1].y, corners[1].z, uvs[1].0, uvs[1].1,
+                corners[2].x, corners[2].y, corners[2].z, uvs[2].0, uvs[2].1,
+                corners[3].x, corners[3].y, corners[3].z);
         }
     }
 
+    /// Draws the image as a quad that always faces the camera.
+    pub fn draw_billboard(&self, img: &Image, pos: &vec3, width: f32, height: f32, camera: &Orientation) {
+        let corners = billboard_corners(pos, &camera.right_vec, &camera.down_vec, width, height);
+        self.draw_textured_quad(img, corners,
+            [(0f32, 0f32), (0f32, img.height as f32), (img.width as f32, img.height as f32)]);
+    }
+
+    /// Like `draw_billboard`, but only draws one frame of a sprite sheet made of
+    /// `frame_width` * `frame_height` sized frames, numbered row by row.
+    /// Nothing is drawn if the sheet has no such frame.
+    pub fn draw_billboard_frame(&self, img: &Image, pos: &vec3, width: f32, height: f32, camera: &Orientation,
+        frame: u32, frame_width: u32, frame_height: u32) {
+        assert!(frame_width > 0 && frame_width <= img.width, "frame_width = {}", frame_width);
+        assert!(frame_height > 0 && frame_height <= img.height, "frame_height = {}", frame_height);
+        let frames_per_row = img.width / frame_width;
+        if frame >= frames_per_row * (img.height / frame_height) {
+            return;
+        }
+        let u0 = ((frame % frames_per_row) * frame_width) as f32;
+        let v0 = ((frame / frames_per_row) * frame_height) as f32;
+        let u1 = u0 + frame_width as f32;
+        let v1 = v0 + frame_height as f32;
+        let corners = billboard_corners(pos, &camera.right_vec, &camera.down_vec, width, height);
+        self.draw_textured_quad(img, corners, [(u0, v0), (u0, v1), (u1, v1)]);
+    }
+
+    /// Billboard which only turns around the vertical axis, so it stays upright
+    /// (trees, explosions) when looked at from above or below.
+    pub fn draw_cylindrical_billboard(&self, img: &Image, pos: &vec3, width: f32, height: f32, camera: &Orientation) {
+        let mut right = vec3::new(camera.right_vec.x, camera.right_vec.y, 0f32);
+        if right.len() < 0.0001 {
+            right = vec3::new(-camera.forward_vec.y, camera.forward_vec.x, 0f32);
+        }
+        let corners = billboard_corners(pos, &right.normalize(), &vec3::new(0f32, 0f32, 1f32), width, height);
+        self.draw_textured_quad(img, corners,
+            [(0f32, 0f32), (0f32, img.height as f32), (img.width as f32, img.height as f32)]);
+    }
+
     pub fn print4x6(&self, x: u32, y: u32, fg_color: Color, bg_color: Color, text: &str) {
         assert!(self.render_dst.in_screen_y(y+5), "y = {}", y);
         let c_str = CString::new(text).expect("CString::new failed");
         let ptr = c_str.as_ptr();
+        // The text is passed as an argument, never as the format string, so '%' is printed as it is
+        let fmt = CString::new("%s").unwrap();
         unsafe {
-            c_api::print4x6(x, y, fg_color.to_i32(), bg_color.to_i32(), ptr);
+            c_api::print4x6(x, y, fg_color.to_i32(), bg_color.to_i32(), fmt.as_ptr(), ptr);
         }
     }
 
@@ -1174,8 +2156,9 @@ impl<'a> RenderContext<'a> {
             None => -1,
             Some(c) => c.to_i32() & 0x00FFFFFF
         };
+        let fmt = CString::new("%s").unwrap();
         unsafe {
-            c_api::print6x8(x, y, fg_color.to_i32(), bg_color, ptr);
+            c_api::print6x8(x, y, fg_color.to_i32(), bg_color, fmt.as_ptr(), ptr);
         }
     }
 
@@ -1193,6 +2176,23 @@ impl<'a> RenderContext<'a> {
         }
     }
 
+    /// Draws the `width` * `height` sized part of the image starting at (`src_x`, `src_y`)
+    /// with its top-left corner at the given screen position. `col` scales the
+    /// texel colors (white leaves them unchanged), the alpha channel of the image is used.
+    pub fn draw_image_region(&self, img: &Image, src_x: u32, src_y: u32, width: u32, height: u32,
+        screen_x: i32, screen_y: i32, zoom: f32, col: Color) {
+        assert!(src_x + width <= img.width && src_y + height <= img.height, "region is outside of the image");
+        let zoom = (zoom * 65536f32) as u32;
+        unsafe {
+            let offset = (src_y * img.bytes_per_line + src_x * 4) as isize;
+            c_api::drawtile(img.ptr.offset(offset) as *const u8, img.bytes_per_line, width, height,
+                0, 0,
+                screen_x as u32, screen_y as u32,
+                zoom, zoom,
+                0, col.to_i32());
+        }
+    }
+
     pub fn save_to_file(&self, filename: &str) {
         unsafe {
             let c_str = CString::new(filename).expect("CString::new failed");
@@ -1215,11 +2215,227 @@ impl<'a> RenderContext<'a> {
             c_api::drawsprite(&spr.ptr);
         }
     }
+
+    /// Draws a KV6 sprite bent or twisted along its x axis (`s`), like
+    /// `drawspritebendx`/`drawspritetwist` of GAME.C: every x-plane slice is
+    /// drawn separately with its own axes. KFA sprites are drawn undeformed.
+    pub fn draw_sprite_deformed(&self, spr: &Sprite, deform: Deform) {
+        const MAX_SLICES: i32 = 32;
+        let (size, pivot) = match spr.kv6_dimensions() {
+            Some(dims) if dims.0.x > 0 => dims,
+            _ => return self.draw_sprite(spr),
+        };
+        let s = vec3::from_point3d(spr.ptr.s);
+        let h = vec3::from_point3d(spr.ptr.h);
+        let f = vec3::from_point3d(spr.ptr.f);
+        let pos = spr.get_pos();
+        let slices = size.x.min(MAX_SLICES);
+        let (old_min, old_max) = unsafe { (c_api::get_xplanemin(), c_api::get_xplanemax()) };
+        for i in 0 .. slices {
+            let plane_min = i * size.x / slices;
+            let plane_max = (i + 1) * size.x / slices;
+            // Center of the slice along x, relative to the pivot
+            let c = (plane_min + plane_max) as f32 * 0.5f32 - pivot.x;
+            let (slice_pos, slice_s, slice_h, slice_f) = match deform {
+                Deform::Twist(amount) => {
+                    let (sin, cos) = (amount * c / size.x as f32).sin_cos();
+                    (pos, s, h * cos + f * sin, f * cos - h * sin)
+                },
+                Deform::BendX(amount) | Deform::BendY(amount) => {
+                    // The x axis follows an arc towards `h` (BendX) or `f` (BendY)
+                    let bend_dir = if let Deform::BendX(_) = deform {h} else {f};
+                    let k = amount / size.x as f32;
+                    let angle = k * c;
+                    let (sin, cos) = angle.sin_cos();
+                    let arc_center = if k.abs() < 0.00001f32 {
+                        pos + s * c
+                    } else {
+                        pos + s * (sin / k) + bend_dir * ((1f32 - cos) / k)
+                    };
+                    let new_s = s * cos + bend_dir * sin;
+                    let new_bend_dir = bend_dir * cos - s * sin;
+                    let (new_h, new_f) = if let Deform::BendX(_) = deform {(new_bend_dir, f)} else {(h, new_bend_dir)};
+                    (arc_center - new_s * c, new_s, new_h, new_f)
+                },
+            };
+            let slice = c_api::vx5sprite {
+                pos: *slice_pos.as_point3d(),
+                flags: spr.ptr.flags,
+                s: *slice_s.as_point3d(),
+                voxnum: spr.ptr.voxnum,
+                h: *slice_h.as_point3d(),
+                kfatim: spr.ptr.kfatim,
+                f: *slice_f.as_point3d(),
+                okfatim: spr.ptr.okfatim,
+            };
+            unsafe {
+                c_api::set_xplanemin(plane_min);
+                c_api::set_xplanemax(plane_max);
+                c_api::drawsprite(&slice);
+            }
+        }
+        unsafe {
+            c_api::set_xplanemin(old_min);
+            c_api::set_xplanemax(old_max);
+        }
+    }
+}
+
+fn billboard_corners(pos: &vec3, right: &vec3, down: &vec3, width: f32, height: f32) -> [vec3; 4] {
+    let half_right = *right * (width * 0.5f32);
+    let half_down = *down * (height * 0.5f32);
+    [*pos - half_right - half_down,
+     *pos - half_right + half_down,
+     *pos + half_right + half_down,
+     *pos + half_right - half_down]
+}
+
+fn project_2d(pos: &vec3) -> ProjecionResult {
+    let mut screen_x = 0f32;
+    let mut screen_y = 0f32;
+    let mut depth = 0f32;
+    let visible = unsafe {
+        c_api::project2d(pos.x, pos.y, pos.z, &mut screen_x, &mut screen_y, &mut depth) == 1
+    };
+    ProjecionResult {
+        screen_x: screen_x.floor() as i32,
+        screen_y: screen_y.floor() as i32,
+        depth: depth,
+        visible: visible
+    }
+}
+
+/// Vertex limit of setlathe, setblobs and setsector (MAXCURS)
+pub const MAX_CURS: usize = 100;
+/// Point limit of sethull3d (MAXPOINTS)
+pub const MAX_HULL_POINTS: usize = 64;
+
+fn validate_hull(points: &[vec3], map_size: i32) -> Result<(), String> {
+    const EPSILON: f32 = 0.001;
+    if points.len() < 4 || points.len() > MAX_HULL_POINTS {
+        return Err(format!("a hull needs 4..{} points, got {}", MAX_HULL_POINTS, points.len()));
+    }
+    check_finite(points)?;
+    let max_xy = map_size as f32;
+    if let Some(p) = points.iter().find(|p| p.x <= 0f32 || p.y <= 0f32 || p.z <= 0f32 || p.x >= max_xy || p.y >= max_xy || p.z >= 256f32) {
+        return Err(format!("{:?} is on or outside the edge of the map", p));
+    }
+    let mut has_volume = false;
+    for i in 0 .. points.len() {
+        for j in i + 1 .. points.len() {
+            if (points[j] - points[i]).len() < EPSILON {
+                return Err(format!("{:?} is duplicated", points[i]));
+            }
+            for k in j + 1 .. points.len() {
+                let normal = (points[j] - points[i]).cross(&(points[k] - points[i]));
+                if normal.len() < EPSILON {
+                    continue;
+                }
+                let normal = normal.normalize();
+                let dist = |p: &vec3| (*p - points[i]).dot(&normal);
+                let (mut above, mut below, mut on_plane) = (false, false, 0);
+                for p in points.iter() {
+                    let d = dist(p);
+                    if d > EPSILON {
+                        above = true;
+                    } else if d < -EPSILON {
+                        below = true;
+                    } else {
+                        on_plane += 1;
+                    }
+                }
+                has_volume |= above || below;
+                // A plane with points only on one side is a face of the hull
+                if above != below && on_plane > 3 {
+                    return Err(format!("{} points are on the same face of the hull", on_plane));
+                }
+            }
+        }
+    }
+    if !has_volume {
+        return Err("the points are on one plane".to_string());
+    }
+    Ok(())
+}
+
+fn validate_lathe(vertices: &[vec3]) -> Result<(), String> {
+    const EPSILON: f32 = 0.001;
+    if vertices.len() < 3 || vertices.len() > MAX_CURS {
+        return Err(format!("a lathe needs 3..{} vertices, got {}", MAX_CURS, vertices.len()));
+    }
+    check_finite(vertices)?;
+    let axis = vertices[1] - vertices[0];
+    if axis.len() < EPSILON {
+        return Err("the first 2 vertices (the axis) are the same".to_string());
+    }
+    let normal = vertices[2 ..].iter()
+        .map(|p| axis.cross(&(*p - vertices[0])))
+        .find(|n| n.len() >= EPSILON)
+        .ok_or_else(|| "the vertices are on one line".to_string())?
+        .normalize();
+    match vertices.iter().find(|p| (**p - vertices[0]).dot(&normal).abs() > EPSILON) {
+        Some(p) => Err(format!("{:?} is not on the plane of the other vertices", p)),
+        None => Ok(()),
+    }
+}
+
+fn check_finite(points: &[vec3]) -> Result<(), String> {
+    match points.iter().find(|p| !(p.x.is_finite() && p.y.is_finite() && p.z.is_finite())) {
+        Some(p) => Err(format!("{:?} is not a finite point", p)),
+        None => Ok(()),
+    }
+}
+
+fn hitscan(pos: &vec3, dir: &vec3) -> Option<HitScanResult> {
+    let mut voxel_pos = ivec3::new(0, 0, 0);
+    let mut face: i32 = 0;
+    unsafe {
+        let mut color_ptr: *mut i32 = ptr::null_mut();
+        c_api::hitscan(&pos.to_dpoint3d(), &dir.to_dpoint3d(), voxel_pos.as_mut_lpoint3d(), &mut color_ptr, &mut face);
+        if color_ptr.is_null() {
+            None
+        } else {
+            Some(HitScanResult {
+                color_ptr: color_ptr,
+                hit_face: match face {
+                    0 => Some(CubeFace::ZMin),
+                    1 => Some(CubeFace::ZMax),
+                    2 => Some(CubeFace::XMin),
+                    3 => Some(CubeFace::XMax),
+                    4 => Some(CubeFace::YMin),
+                    5 => Some(CubeFace::YMax),
+                    _ => None,  // -1 if inside solid
+                },
+                pos: voxel_pos,
+            })
+        }
+    }
+}
+
+// `max_scale` is the scanned distance as a multiple of the length of `dir`
+fn sprhitscan(pos: &vec3, dir: &vec3, spr: &Sprite, max_scale: f32) -> Option<SprHitScanResult> {
+    let mut voxel_pos = ivec3::new(0, 0, 0);
+    let mut scale = max_scale;
+    unsafe {
+        let mut kv6voxtype_ptr: *mut c_api::kv6voxtype = ptr::null_mut();
+        c_api::sprhitscan(&pos.to_dpoint3d(), &dir.to_dpoint3d(), &spr.ptr, voxel_pos.as_mut_lpoint3d(), &mut kv6voxtype_ptr, &mut scale);
+        if kv6voxtype_ptr.is_null() {
+            None
+        } else {
+            Some(SprHitScanResult {
+                //color: Color::from_i32(*color_ptr),
+                pos: voxel_pos,
+                distance: scale * dir.len(),
+            })
+        }
+    }
 }
 
+/// Screen coordinates can be negative or larger than the screen if the
+/// point is outside of the view.
 pub struct ProjecionResult {
-    pub screen_x: u32,
-    pub screen_y: u32,
+    pub screen_x: i32,
+    pub screen_y: i32,
     pub depth: f32,
     pub visible: bool
 }
@@ -1253,6 +2469,7 @@ pub enum VisibilityResult {
     CannotSee(ivec3),
 }
 
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum CubeFace {
     ZMin,
     ZMax,
@@ -1270,23 +2487,56 @@ pub struct HitScanResult {
 }
 
 impl HitScanResult {
-    pub fn set_color(&mut self, color: Color) {
+    pub fn set_color(&mut self, color: VoxelColor) {
         unsafe {
             *self.color_ptr = color.to_i32();
         }
     }
 
-    pub fn get_color(&self) -> Color {
+    pub fn get_color(&self) -> VoxelColor {
         unsafe {
-            Color::from_i32(*self.color_ptr)
+            VoxelColor::from_i32(*self.color_ptr)
         }
     }
 }
 
+/// A voxel picked by `RenderContext::pick_voxel`, with its color at the time of the pick.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct VoxelPickResult {
+    pub hit_face: Option<CubeFace>,
+    pub pos: ivec3,
+    pub color: VoxelColor,
+}
+
+
+/// Deformations for `RenderContext::draw_sprite_deformed`. The amount is the
+/// angle in radians between the two ends of the sprite.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Deform {
+    /// Bends the x axis towards the y axis (`h`) of the sprite
+    BendX(f32),
+    /// Bends the x axis towards the z axis (`f`) of the sprite
+    BendY(f32),
+    /// Twists the slices around the x axis
+    Twist(f32),
+}
+
+pub struct SxlScene {
+    pub vxl_file: String,
+    /// Empty if the scene has no sky
+    pub sky_file: String,
+    /// The global user string of the scene, empty if there is none
+    pub user_string: String,
+    pub sprites: Vec<Sprite>,
+    /// The user strings of `sprites`, in the same order
+    pub sprite_user_strings: Vec<String>,
+}
 
 pub struct SprHitScanResult {
     //pub color: Color,
     pub pos: ivec3,
+    /// Distance of the hit from the start of the ray
+    pub distance: f32,
 }
 
 
@@ -1336,3 +2586,212 @@ impl DrawTileBuilder {
 pub fn draw_tile() -> DrawTileBuilder {
     DrawTileBuilder::new()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(min: f32, max: f32) -> Vec<vec3> {
+        let mut points = vec![];
+        for &z in [min, max].iter() {
+            for &(x, y) in [(min, min), (max, min), (max, max), (min, max)].iter() {
+                points.push(vec3::new(x, y, z));
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn hull_tetrahedron() {
+        let points = [vec3::new(10f32, 10f32, 10f32), vec3::new(20f32, 10f32, 10f32),
+            vec3::new(10f32, 20f32, 10f32), vec3::new(10f32, 10f32, 20f32)];
+        assert_eq!(validate_hull(&points, 1024), Ok(()));
+    }
+
+    #[test]
+    fn hull_point_count() {
+        assert!(validate_hull(&[], 1024).is_err());
+        assert!(validate_hull(&cube(10f32, 20f32)[.. 3], 1024).is_err());
+        let too_many = (0 .. MAX_HULL_POINTS + 1).map(|i| vec3::new(10f32 + i as f32, 10f32 + (i * i) as f32 % 7f32, 10f32)).collect::<Vec<_>>();
+        assert!(validate_hull(&too_many, 1024).is_err());
+    }
+
+    #[test]
+    fn hull_map_edges() {
+        let inside = [vec3::new(1f32, 1f32, 1f32), vec3::new(1023f32, 1f32, 1f32),
+            vec3::new(1f32, 1023f32, 1f32), vec3::new(1f32, 1f32, 255f32)];
+        assert_eq!(validate_hull(&inside, 1024), Ok(()));
+        let mut on_edge = inside;
+        on_edge[1].x = 1024f32;
+        assert!(validate_hull(&on_edge, 1024).is_err());
+        let mut on_top = inside;
+        on_top[0].z = 0f32;
+        assert!(validate_hull(&on_top, 1024).is_err());
+        let mut nan = inside;
+        nan[2].y = f32::NAN;
+        assert!(validate_hull(&nan, 1024).is_err());
+    }
+
+    #[test]
+    fn hull_coplanar_points() {
+        let flat = [vec3::new(10f32, 10f32, 10f32), vec3::new(20f32, 10f32, 10f32),
+            vec3::new(20f32, 20f32, 10f32), vec3::new(10f32, 20f32, 10f32)];
+        assert_eq!(validate_hull(&flat, 1024), Err("the points are on one plane".to_string()));
+        // Every face of a box has 4 points
+        assert!(validate_hull(&cube(10f32, 20f32), 1024).is_err());
+    }
+
+    #[test]
+    fn hull_duplicated_point() {
+        let mut points = cube(10f32, 20f32)[.. 4].to_vec();
+        points[3] = points[0];
+        assert!(validate_hull(&points, 1024).unwrap_err().contains("duplicated"));
+    }
+
+    #[test]
+    fn lathe_vertices() {
+        let profile = [vec3::new(10f32, 10f32, 10f32), vec3::new(10f32, 10f32, 30f32),
+            vec3::new(15f32, 10f32, 20f32), vec3::new(12f32, 10f32, 25f32)];
+        assert_eq!(validate_lathe(&profile), Ok(()));
+        assert!(validate_lathe(&profile[.. 2]).is_err());
+        let mut same_axis = profile;
+        same_axis[1] = same_axis[0];
+        assert!(validate_lathe(&same_axis).is_err());
+        let line = [profile[0], profile[1], vec3::new(10f32, 10f32, 20f32)];
+        assert_eq!(validate_lathe(&line), Err("the vertices are on one line".to_string()));
+        let mut off_plane = profile;
+        off_plane[3].y = 11f32;
+        assert!(validate_lathe(&off_plane).is_err());
+    }
+
+    fn assert_vec_eq(a: &vec3, b: &vec3) {
+        assert!((*a - *b).len() < 0.001, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_matrix_eq(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) {
+        for i in 0 .. 3 {
+            for j in 0 .. 3 {
+                assert!((a[i][j] - b[i][j]).abs() < 0.001, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    /// Rotation by `angle` radians around the normalized `axis` (Rodrigues' formula).
+    fn axis_angle_matrix(axis: &vec3, angle: f32) -> [[f32; 3]; 3] {
+        let a = axis.normalize();
+        let (sin, cos) = angle.sin_cos();
+        let t = 1f32 - cos;
+        [[cos + a.x * a.x * t, a.x * a.y * t - a.z * sin, a.x * a.z * t + a.y * sin],
+         [a.y * a.x * t + a.z * sin, cos + a.y * a.y * t, a.y * a.z * t - a.x * sin],
+         [a.z * a.x * t - a.y * sin, a.z * a.y * t + a.x * sin, cos + a.z * a.z * t]]
+    }
+
+    #[test]
+    fn quaternion_round_trip() {
+        let pi = std::f32::consts::PI;
+        let rotations = [
+            // trace > 0
+            (vec3::new(0f32, 0f32, 1f32), 0f32),
+            (vec3::new(1f32, 2f32, 3f32), 0.7f32),
+            // the largest diagonal element is m[0][0], m[1][1] and m[2][2]
+            (vec3::new(1f32, 0f32, 0f32), pi),
+            (vec3::new(1f32, 0.2f32, 0.1f32), 2.9f32),
+            (vec3::new(0f32, 1f32, 0f32), pi),
+            (vec3::new(0.1f32, 1f32, 0.3f32), 2.8f32),
+            (vec3::new(0f32, 0f32, 1f32), pi),
+            (vec3::new(0.2f32, -0.1f32, 1f32), 3f32)];
+        let pos = vec3::new(1f32, 2f32, 3f32);
+        for &(axis, angle) in rotations.iter() {
+            let m = axis_angle_matrix(&axis, angle);
+            let q = Orientation::from_matrix(&pos, &m).to_quaternion();
+            let len = (q.x * q.x + q.y * q.y + q.z * q.z + q.w * q.w).sqrt();
+            assert!((len - 1f32).abs() < 0.001, "{:?} is not a unit quaternion", q);
+            let ori = Orientation::from_quaternion(&pos, &q);
+            assert_matrix_eq(&ori.to_matrix(), &m);
+            assert_eq!(ori.pos, pos);
+        }
+    }
+
+    #[test]
+    fn yaw_pitch_roll_round_trip() {
+        let pos = vec3::new(5f32, 6f32, 7f32);
+        for &(yaw, pitch) in [(0f32, 0f32), (1f32, 0.5f32), (-2.5f32, -1.2f32), (3f32, 1.5f32)].iter() {
+            let ori = Orientation::from_yaw_pitch_roll(&pos, yaw, pitch, 0f32);
+            let (y, p, r) = ori.yaw_pitch_roll();
+            assert!((y - yaw).abs() < 0.001 && (p - pitch).abs() < 0.001 && r.abs() < 0.001, "{} {} {}", y, p, r);
+            let back = Orientation::from_yaw_pitch_roll(&pos, y, p, r);
+            assert_matrix_eq(&back.to_matrix(), &ori.to_matrix());
+        }
+    }
+
+    #[test]
+    fn yaw_pitch_roll_finds_the_roll() {
+        let level = Orientation::from_yaw_pitch_roll(&vec3::new(0f32, 0f32, 0f32), 0.3f32, 0.2f32, 0f32);
+        let (sin, cos) = 0.4f32.sin_cos();
+        let rolled = Orientation::from_axes(&level.pos,
+            &(level.right_vec * cos + level.down_vec * sin),
+            &(level.down_vec * cos - level.right_vec * sin),
+            &level.forward_vec);
+        let (yaw, pitch, roll) = rolled.yaw_pitch_roll();
+        assert!((yaw - 0.3f32).abs() < 0.001 && (pitch - 0.2f32).abs() < 0.001, "{} {}", yaw, pitch);
+        assert!((roll.abs() - 0.4f32).abs() < 0.001, "{}", roll);
+    }
+
+    #[test]
+    fn look_at_basis() {
+        let pos = vec3::new(10f32, 20f32, 30f32);
+        let up = vec3::new(0f32, 0f32, -1f32);
+        for target in [vec3::new(50f32, -3f32, 12f32), vec3::new(10f32, 20f32, 100f32), vec3::new(10f32, 20f32, 0f32)].iter() {
+            let ori = Orientation::look_at(&pos, target, &up);
+            assert_vec_eq(&ori.forward_vec, &(*target - pos).normalize());
+            for v in [ori.right_vec, ori.down_vec, ori.forward_vec].iter() {
+                assert!((v.len() - 1f32).abs() < 0.001, "{:?} is not a unit vector", v);
+            }
+            assert!(ori.right_vec.dot(&ori.down_vec).abs() < 0.001);
+            assert!(ori.right_vec.dot(&ori.forward_vec).abs() < 0.001);
+            assert!(ori.down_vec.dot(&ori.forward_vec).abs() < 0.001);
+            // right x down = forward
+            assert_vec_eq(&ori.right_vec.cross(&ori.down_vec), &ori.forward_vec);
+        }
+        // The down vector points down (+z) when looking horizontally
+        let ori = Orientation::look_at(&pos, &vec3::new(0f32, 20f32, 30f32), &up);
+        assert!(ori.down_vec.z > 0.999f32, "{:?}", ori.down_vec);
+    }
+
+    #[test]
+    fn voxel_color_i32_round_trip() {
+        for &col in [VoxelColor::new(1, 2, 3, 4), VoxelColor::new(255, 0, 128, 255),
+                     VoxelColor::new(0, 0, 0, 0), VoxelColor::rgb(12, 34, 56)].iter() {
+            assert_eq!(VoxelColor::from_i32(col.to_i32()), col);
+        }
+        assert_eq!(VoxelColor::new(0x12, 0x34, 0x56, 0x78).to_i32(), 0x78123456);
+        // Intensities from 0x80 make the value negative
+        assert_eq!(VoxelColor::from_i32(0xFF102030u32 as i32), VoxelColor::new(0x10, 0x20, 0x30, 0xFF));
+    }
+
+    #[test]
+    fn voxel_color_rgb_is_neutral() {
+        let col = VoxelColor::rgb(0x10, 0x20, 0x30);
+        assert_eq!(col.intensity, VoxelColor::NEUTRAL_INTENSITY);
+        assert_eq!((col.to_i32() >> 24) & 0xFF, 0x80);
+        assert_eq!(VoxelColor::from_color(Color::rgba(0x10, 0x20, 0x30, 0)), col);
+    }
+
+    #[test]
+    fn voxel_color_shading() {
+        assert_eq!(VoxelColor::rgb(10, 100, 255).shaded_color(), Color::rgb(10, 100, 255));
+        // About twice as bright, clamped to 255
+        assert_eq!(VoxelColor::new(10, 100, 255, 0xFF).shaded_color(), Color::rgb(19, 199, 255));
+        assert_eq!(VoxelColor::new(10, 100, 255, 0).shaded_color(), Color::rgb(0, 0, 0));
+        assert_eq!(VoxelColor::rgb(10, 20, 30).shade(0.5f32).intensity, 0x40);
+        assert_eq!(VoxelColor::rgb(10, 20, 30).shade(4f32).intensity, 0xFF);
+    }
+
+    #[test]
+    fn voxel_to_i32() {
+        assert_eq!(Voxel::Air.to_i32(), -1);
+        assert_eq!(Voxel::ColorFunction.to_i32(), -2);
+        let col = VoxelColor::rgb(1, 2, 3);
+        assert_eq!(Voxel::Solid(col).to_i32(), col.to_i32());
+    }
+}
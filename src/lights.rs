@@ -0,0 +1,176 @@
+use c_api;
+use {ivec3, vec3, Voxlap};
+
+pub use c_api::MAXLIGHTS;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct PointLight {
+    pub pos: vec3,
+    /// Voxels outside of the radius are not lit by the light
+    pub radius: f32,
+    /// Brightness scale, GAME.C uses 262144 for its lights
+    pub intensity: f32,
+}
+
+impl PointLight {
+    pub fn new(pos: &vec3, radius: f32, intensity: f32) -> PointLight {
+        PointLight {
+            pos: *pos,
+            radius: radius,
+            intensity: intensity,
+        }
+    }
+
+    // The box of voxels lit by the light, max is exclusive
+    fn bounds(&self) -> (ivec3, ivec3) {
+        let r = self.radius.ceil() as i32 + 1;
+        let (x, y, z) = (self.pos.x as i32, self.pos.y as i32, self.pos.z as i32);
+        (ivec3::new(x - r, y - r, z - r), ivec3::new(x + r + 1, y + r + 1, z + r + 1))
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub struct LightId(u32);
+
+/// Point lights of `LightingMode::MultiplePointSourceLighting`. Changes are
+/// collected and applied by `update`, which relights only the boxes around
+/// the lights that were added, moved or removed since the last update.
+pub struct LightManager {
+    lights: Vec<(LightId, PointLight)>,
+    next_id: u32,
+    dirty: Vec<(ivec3, ivec3)>,
+}
+
+impl LightManager {
+    pub fn new() -> LightManager {
+        LightManager {
+            lights: vec![],
+            next_id: 0,
+            dirty: vec![],
+        }
+    }
+
+    /// None if there are already `MAXLIGHTS` lights.
+    pub fn add(&mut self, light: PointLight) -> Option<LightId> {
+        if self.lights.len() >= MAXLIGHTS {
+            return None;
+        }
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.dirty.push(light.bounds());
+        self.lights.push((id, light));
+        Some(id)
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<PointLight> {
+        let index = self.lights.iter().position(|&(light_id, _)| light_id == id)?;
+        let (_, light) = self.lights.remove(index);
+        self.dirty.push(light.bounds());
+        Some(light)
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&PointLight> {
+        self.lights.iter().find(|&&(light_id, _)| light_id == id).map(|(_, light)| light)
+    }
+
+    /// Replaces the light, returns false if there is no light with the id.
+    pub fn set(&mut self, id: LightId, light: PointLight) -> bool {
+        match self.lights.iter_mut().find(|&&mut (light_id, _)| light_id == id) {
+            None => false,
+            Some(entry) => {
+                if entry.1 != light {
+                    self.dirty.push(entry.1.bounds());
+                    self.dirty.push(light.bounds());
+                    entry.1 = light;
+                }
+                true
+            }
+        }
+    }
+
+    pub fn move_light(&mut self, id: LightId, pos: &vec3) -> bool {
+        match self.get(id) {
+            None => false,
+            Some(light) => {
+                let light = PointLight {pos: *pos, ..*light};
+                self.set(id, light)
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for (_, light) in self.lights.iter() {
+            self.dirty.push(light.bounds());
+        }
+        self.lights.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Relights the whole map with the current lights on the next `update`,
+    /// e.g. after loading a new map.
+    pub fn mark_all_dirty(&mut self, vox: &Voxlap) {
+        let size = vox.get_max_xy_dimension();
+        self.dirty = vec![(ivec3::new(0, 0, 0), ivec3::new(size, size, 256))];
+    }
+
+    /// Uploads the lights to the engine and calls `update_lighting` on the
+    /// changed boxes. Returns the number of relit boxes.
+    pub fn update(&mut self, vox: &mut Voxlap) -> usize {
+        unsafe {
+            let lightsrc = c_api::get_lightsrc();
+            for (i, (_, light)) in self.lights.iter().enumerate() {
+                *lightsrc.add(i) = c_api::lightsrctype {
+                    p: *light.pos.as_point3d(),
+                    r2: light.radius * light.radius,
+                    sc: light.intensity,
+                };
+            }
+            c_api::set_numlights(self.lights.len() as i32);
+        }
+        let size = vox.get_max_xy_dimension();
+        let boxes = merge_boxes(self.dirty.drain(..).collect());
+        for (min, max) in boxes.iter() {
+            let (x0, y0, z0) = (min.x.max(0), min.y.max(0), min.z.max(0));
+            let (x1, y1, z1) = (max.x.min(size), max.y.min(size), max.z.min(256));
+            if x0 < x1 && y0 < y1 && z0 < z1 {
+                vox.update_lighting(x0, y0, z0, x1, y1, z1);
+            }
+        }
+        boxes.len()
+    }
+}
+
+impl Default for LightManager {
+    fn default() -> LightManager {
+        LightManager::new()
+    }
+}
+
+/// Joins the overlapping boxes, so no voxel is relit twice.
+fn merge_boxes(mut boxes: Vec<(ivec3, ivec3)>) -> Vec<(ivec3, ivec3)> {
+    let overlaps = |a: &(ivec3, ivec3), b: &(ivec3, ivec3)|
+        a.0.x < b.1.x && b.0.x < a.1.x && a.0.y < b.1.y && b.0.y < a.1.y && a.0.z < b.1.z && b.0.z < a.1.z;
+    let mut merged: Vec<(ivec3, ivec3)> = vec![];
+    while let Some(mut current) = boxes.pop() {
+        // A grown box can overlap boxes which were already checked, so start over
+        while let Some(i) = merged.iter().position(|other| overlaps(&current, other)) {
+            let other = merged.swap_remove(i);
+            current = (
+                ivec3::new(current.0.x.min(other.0.x), current.0.y.min(other.0.y), current.0.z.min(other.0.z)),
+                ivec3::new(current.1.x.max(other.1.x), current.1.y.max(other.1.y), current.1.z.max(other.1.z)));
+        }
+        merged.push(current);
+    }
+    merged
+}
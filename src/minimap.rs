@@ -0,0 +1,185 @@
+use std::cmp;
+
+use ui::Rect;
+use world::MAX_Z;
+use {ivec3, Color, Image, VoxelColor, Voxlap};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct MinimapOptions {
+    /// Higher columns are drawn brighter
+    pub height_shading: bool,
+    /// Draws a contour line at every `interval` voxels of height, intervals
+    /// below 1 are ignored
+    pub contour_interval: Option<i32>,
+    pub contour_color: Color,
+}
+
+impl Default for MinimapOptions {
+    fn default() -> MinimapOptions {
+        MinimapOptions {
+            height_shading: false,
+            contour_interval: None,
+            contour_color: Color::black(),
+        }
+    }
+}
+
+/// Orthographic top-down image of a map region. Every pixel shows the
+/// highest voxel of a `scale` * `scale` block of columns. After editing the
+/// map, mark the changed area with `mark_dirty` and call `update` to redraw
+/// only those pixels.
+pub struct Minimap {
+    region: Rect,
+    scale: u32,
+    options: MinimapOptions,
+    image: Image,
+    // in pixel coordinates
+    dirty: Vec<Rect>,
+}
+
+impl Minimap {
+    /// `region` is in map coordinates (x, y of the columns).
+    pub fn new(vox: &Voxlap, region: &Rect, scale: u32, options: MinimapOptions) -> Minimap {
+        Minimap {
+            region: *region,
+            scale: scale,
+            options: options,
+            image: render(vox, region, scale, &options),
+            dirty: vec![],
+        }
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn region(&self) -> Rect {
+        self.region
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn options(&self) -> &MinimapOptions {
+        &self.options
+    }
+
+    /// The whole image is redrawn on the next `update`.
+    pub fn set_options(&mut self, options: MinimapOptions) {
+        self.options = options;
+        self.dirty = vec![Rect::new(0, 0, self.image.width as i32, self.image.height as i32)];
+    }
+
+    /// Marks the columns of `area` (map coordinates) as changed, e.g. the
+    /// bounding box of a CSG operation.
+    pub fn mark_dirty(&mut self, area: &Rect) {
+        let scale = self.scale as i32;
+        let x0 = (area.x - self.region.x).div_euclid(scale);
+        let y0 = (area.y - self.region.y).div_euclid(scale);
+        let x1 = (area.right() - self.region.x + scale - 1).div_euclid(scale);
+        let y1 = (area.bottom() - self.region.y + scale - 1).div_euclid(scale);
+        // Contour lines depend on the neighbouring pixels too
+        let pixels = Rect::new(x0 - 1, y0 - 1, x1 - x0 + 2, y1 - y0 + 2)
+            .intersect(&Rect::new(0, 0, self.image.width as i32, self.image.height as i32));
+        if !pixels.is_empty() {
+            self.dirty.push(pixels);
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Redraws the dirty parts of the image.
+    pub fn update(&mut self, vox: &Voxlap) {
+        for pixels in self.dirty.drain(..) {
+            render_pixels(vox, &self.region, self.scale, &self.options, &mut self.image, &pixels);
+        }
+    }
+}
+
+/// Renders `region` (map coordinates) into a new image of
+/// `region.width / scale` * `region.height / scale` pixels (rounded up).
+pub fn render(vox: &Voxlap, region: &Rect, scale: u32, options: &MinimapOptions) -> Image {
+    assert!(scale > 0, "scale must be > 0");
+    let scale = scale as i32;
+    let width = cmp::max((region.width + scale - 1) / scale, 0) as u32;
+    let height = cmp::max((region.height + scale - 1) / scale, 0) as u32;
+    let mut image = Image::new(width, height);
+    let pixels = Rect::new(0, 0, width as i32, height as i32);
+    render_pixels(vox, region, scale as u32, options, &mut image, &pixels);
+    image
+}
+
+/// Height and color of the highest voxel in the block of columns under the
+/// pixel. Columns outside of the map count as empty.
+fn sample(vox: &Voxlap, region: &Rect, scale: i32, px: i32, py: i32) -> (i32, Option<VoxelColor>) {
+    let size = vox.get_max_xy_dimension();
+    let block = Rect::new(region.x + px * scale, region.y + py * scale, scale, scale)
+        .intersect(region)
+        .intersect(&Rect::new(0, 0, size, size));
+    if block.is_empty() {
+        return (MAX_Z, None);
+    }
+    let mut top = (i32::MAX, 0, 0);
+    for y in block.y .. block.bottom() {
+        for x in block.x .. block.right() {
+            let z = vox.get_floor_z(&ivec3::new(x, y, 0));
+            if z < top.0 {
+                top = (z, x, y);
+            }
+        }
+    }
+    (top.0, vox.get_cube(top.1, top.2, top.0))
+}
+
+fn render_pixels(vox: &Voxlap, region: &Rect, scale: u32, options: &MinimapOptions, image: &mut Image, pixels: &Rect) {
+    let scale = scale as i32;
+    // One pixel border around the rect for the contour lines
+    let bounds = Rect::new(0, 0, image.width as i32, image.height as i32);
+    let sampled = Rect::new(pixels.x, pixels.y, pixels.width + 1, pixels.height + 1).intersect(&bounds);
+    let mut samples = Vec::with_capacity((sampled.width * sampled.height) as usize);
+    for py in sampled.y .. sampled.bottom() {
+        for px in sampled.x .. sampled.right() {
+            samples.push(sample(vox, region, scale, px, py));
+        }
+    }
+    let height_at = |px: i32, py: i32| {
+        let px = cmp::min(px, sampled.right() - 1);
+        let py = cmp::min(py, sampled.bottom() - 1);
+        samples[((py - sampled.y) * sampled.width + px - sampled.x) as usize].0
+    };
+
+    for py in pixels.y .. pixels.bottom() {
+        for px in pixels.x .. pixels.right() {
+            let (z, col) = samples[((py - sampled.y) * sampled.width + px - sampled.x) as usize];
+            let on_contour = match options.contour_interval {
+                Some(interval) if interval > 0 => {
+                    let level = z.div_euclid(interval);
+                    // The line goes on the higher side (smaller z) of the step
+                    level < height_at(px + 1, py).div_euclid(interval) || level < height_at(px, py + 1).div_euclid(interval)
+                },
+                _ => false,
+            };
+            let pixel = if on_contour {
+                options.contour_color
+            } else {
+                match col {
+                    None => Color::black(),
+                    Some(col) => shade(col, z, options.height_shading),
+                }
+            };
+            image.set_pixel(px as u32, py as u32, pixel);
+        }
+    }
+}
+
+/// Applies the intensity byte of the voxel and the optional height shading.
+fn shade(col: VoxelColor, z: i32, height_shading: bool) -> Color {
+    if height_shading {
+        col.shade(1f32 + (128 - z) as f32 / 256f32 * 0.6f32).shaded_color()
+    } else {
+        col.shaded_color()
+    }
+}
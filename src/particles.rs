@@ -0,0 +1,285 @@
+use rand::{self, Rng};
+
+use {ivec3, vec3, Color, RenderContext, Voxlap};
+
+#[derive(Clone, Debug)]
+pub enum EmitterShape {
+    Point,
+    /// Spawns inside a sphere around the emitter position, moving outwards.
+    Sphere { radius: f32 },
+    /// Spawns at the emitter position, moving inside a cone around `dir`.
+    Cone { dir: vec3, half_angle: f32 },
+    /// Spawns on the exposed side of a map voxel, moving along its estimated normal
+    /// (dust from a bullet hole, debris from an explosion).
+    VoxelSurface { voxel: ivec3, spread: f32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    pub pos: vec3,
+    pub shape: EmitterShape,
+    /// Particles per second, 0 for emitters used only through `burst`.
+    pub rate: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub min_lifetime: f32,
+    pub max_lifetime: f32,
+    accumulator: f32,
+}
+
+impl Emitter {
+    pub fn new(pos: vec3, shape: EmitterShape) -> Emitter {
+        Emitter {
+            pos: pos,
+            shape: shape,
+            rate: 0f32,
+            min_speed: 1f32,
+            max_speed: 1f32,
+            min_lifetime: 1f32,
+            max_lifetime: 1f32,
+            accumulator: 0f32,
+        }
+    }
+
+    pub fn rate(mut self, param: f32) -> Emitter {self.rate = param; self }
+    pub fn speed(mut self, min: f32, max: f32) -> Emitter {self.min_speed = min; self.max_speed = max; self }
+    pub fn lifetime(mut self, min: f32, max: f32) -> Emitter {self.min_lifetime = min; self.max_lifetime = max; self }
+
+    fn spawn(&self, vox: &Voxlap) -> Particle {
+        let mut rng = rand::thread_rng();
+        let (pos, dir) = match self.shape {
+            EmitterShape::Point => (self.pos, rng.gen::<vec3>()),
+            EmitterShape::Sphere { radius } => {
+                let dir = rng.gen::<vec3>();
+                (self.pos + dir * (radius * rng.gen::<f32>().cbrt()), dir)
+            },
+            EmitterShape::Cone { dir, half_angle } => (self.pos, random_in_cone(&mut rng, &dir.normalize(), half_angle)),
+            EmitterShape::VoxelSurface { voxel, spread } => {
+                let normal = vox.estimate_normal_vector(&voxel);
+                (voxel.to_vec3() + normal * 0.5f32, random_in_cone(&mut rng, &normal, spread))
+            },
+        };
+        let speed = lerp(self.min_speed, self.max_speed, rng.gen());
+        Particle {
+            pos: pos,
+            vel: dir * speed,
+            age: 0f32,
+            lifetime: lerp(self.min_lifetime, self.max_lifetime, rng.gen()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Particle {
+    pub pos: vec3,
+    pub vel: vec3,
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+/// One particle effect (sparks, smoke, ...): every particle shares the physics
+/// parameters and the color/size ramps, while the emitters decide where they come from.
+pub struct ParticleSystem {
+    pub emitters: Vec<Emitter>,
+    particles: Vec<Particle>,
+    /// Acceleration in voxels/s², the map's z axis points down.
+    pub gravity: vec3,
+    /// Fraction of the velocity lost per second.
+    pub drag: f32,
+    /// Fraction of the velocity kept after hitting the map, None to kill particles on impact.
+    pub bounce: Option<f32>,
+    pub max_particles: usize,
+    color_ramp: Vec<(f32, Color)>,
+    size_ramp: Vec<(f32, f32)>,
+    time_step: f32,
+    accumulator: f32,
+}
+
+impl ParticleSystem {
+    pub fn new() -> ParticleSystem {
+        ParticleSystem {
+            emitters: vec![],
+            particles: vec![],
+            gravity: vec3::new(0f32, 0f32, 20f32),
+            drag: 0f32,
+            bounce: Some(0.5f32),
+            max_particles: 4096,
+            color_ramp: vec![(0f32, Color::white())],
+            size_ramp: vec![(0f32, 0f32)],
+            time_step: 1f32 / 60f32,
+            accumulator: 0f32,
+        }
+    }
+
+    /// Color over the lifetime of a particle: (age / lifetime, color) pairs in increasing order.
+    pub fn set_color_ramp(&mut self, ramp: &[(f32, Color)]) {
+        assert!(!ramp.is_empty(), "The color ramp cannot be empty");
+        self.color_ramp = ramp.to_vec();
+    }
+
+    /// Sphere radius over the lifetime of a particle. Particles with a radius
+    /// below 0.5 are drawn as single points.
+    pub fn set_size_ramp(&mut self, ramp: &[(f32, f32)]) {
+        assert!(!ramp.is_empty(), "The size ramp cannot be empty");
+        self.size_ramp = ramp.to_vec();
+    }
+
+    pub fn set_time_step(&mut self, step: f32) {
+        assert!(step > 0f32, "step must be > 0");
+        self.time_step = step;
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    /// Spawns `count` particles at once from the given emitter.
+    pub fn burst(&mut self, vox: &Voxlap, emitter_index: usize, count: u32) {
+        for _ in 0 .. count {
+            if self.particles.len() >= self.max_particles {
+                return;
+            }
+            let particle = self.emitters[emitter_index].spawn(vox);
+            self.particles.push(particle);
+        }
+    }
+
+    /// Advances the simulation by `dt` seconds in fixed steps. The remainder is
+    /// kept for the next call.
+    pub fn update(&mut self, vox: &mut Voxlap, dt: f32) {
+        self.accumulator += dt;
+        while self.accumulator >= self.time_step {
+            self.accumulator -= self.time_step;
+            let step = self.time_step;
+            self.step(vox, step);
+        }
+    }
+
+    fn step(&mut self, vox: &mut Voxlap, dt: f32) {
+        for i in 0 .. self.emitters.len() {
+            self.emitters[i].accumulator += self.emitters[i].rate * dt;
+            while self.emitters[i].accumulator >= 1f32 {
+                self.emitters[i].accumulator -= 1f32;
+                if self.particles.len() < self.max_particles {
+                    let particle = self.emitters[i].spawn(vox);
+                    self.particles.push(particle);
+                }
+            }
+        }
+
+        let drag = (1f32 - self.drag * dt).max(0f32);
+        let gravity = self.gravity;
+        let bounce = self.bounce;
+        let mut i = 0;
+        while i < self.particles.len() {
+            let alive = {
+                let p = &mut self.particles[i];
+                p.age += dt;
+                if p.age >= p.lifetime {
+                    false
+                } else {
+                    p.vel = (p.vel + gravity * dt) * drag;
+                    let movement = p.vel * dt;
+                    move_particle(vox, p, &movement, bounce)
+                }
+            };
+            if alive {
+                i += 1;
+            } else {
+                self.particles.swap_remove(i);
+            }
+        }
+    }
+
+    pub fn draw(&self, render_context: &RenderContext) {
+        for p in self.particles.iter() {
+            let t = (p.age / p.lifetime).min(1f32);
+            let col = sample_color(&self.color_ramp, t);
+            let size = sample_f32(&self.size_ramp, t);
+            if size < 0.5f32 {
+                render_context.draw_point_3d(&p.pos, col);
+            } else {
+                render_context.draw_sphere_with_z_buffer(&p.pos, size, col);
+            }
+        }
+    }
+}
+
+impl Default for ParticleSystem {
+    fn default() -> ParticleSystem {
+        ParticleSystem::new()
+    }
+}
+
+/// Returns false if the particle has to be removed.
+fn move_particle(vox: &mut Voxlap, p: &mut Particle, movement: &vec3, bounce: Option<f32>) -> bool {
+    let dist = movement.len();
+    if dist == 0f32 {
+        return true;
+    }
+    let hit_pos = vox.hitscan(&p.pos, movement).map(|hit| hit.pos);
+    match hit_pos {
+        Some(hit_pos) if (hit_pos.to_vec3() - p.pos).len() <= dist + 0.87f32 => {
+            match bounce {
+                None => false,
+                Some(bounce) => {
+                    let normal = vox.estimate_normal_vector(&hit_pos);
+                    let along_normal = p.vel.dot(&normal);
+                    if along_normal < 0f32 {
+                        p.vel = (p.vel - normal * (2f32 * along_normal)) * bounce;
+                    }
+                    true
+                }
+            }
+        },
+        _ => {
+            p.pos = p.pos + *movement;
+            true
+        }
+    }
+}
+
+fn random_in_cone<R: Rng>(rng: &mut R, axis: &vec3, half_angle: f32) -> vec3 {
+    let cos_t = lerp(half_angle.cos(), 1f32, rng.gen());
+    let sin_t = (1f32 - cos_t * cos_t).max(0f32).sqrt();
+    let phi = rng.gen::<f32>() * 2f32 * std::f32::consts::PI;
+    let helper = if axis.x.abs() < 0.9f32 {vec3::new(1f32, 0f32, 0f32)} else {vec3::new(0f32, 1f32, 0f32)};
+    let u = axis.cross(&helper).normalize();
+    let v = axis.cross(&u);
+    *axis * cos_t + u * (sin_t * phi.cos()) + v * (sin_t * phi.sin())
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn ramp_segment<T: Copy>(ramp: &[(f32, T)], t: f32) -> (T, T, f32) {
+    if t <= ramp[0].0 {
+        return (ramp[0].1, ramp[0].1, 0f32);
+    }
+    for pair in ramp.windows(2) {
+        let (t0, a) = pair[0];
+        let (t1, b) = pair[1];
+        if t <= t1 {
+            let f = if t1 > t0 {(t - t0) / (t1 - t0)} else {1f32};
+            return (a, b, f);
+        }
+    }
+    let last = ramp[ramp.len() - 1].1;
+    (last, last, 0f32)
+}
+
+fn sample_f32(ramp: &[(f32, f32)], t: f32) -> f32 {
+    let (a, b, f) = ramp_segment(ramp, t);
+    lerp(a, b, f)
+}
+
+fn sample_color(ramp: &[(f32, Color)], t: f32) -> Color {
+    let (a, b, f) = ramp_segment(ramp, t);
+    let channel = |x: u8, y: u8| lerp(x as f32, y as f32, f).round() as u8;
+    Color::rgba(channel(a.r, b.r), channel(a.g, b.g), channel(a.b, b.b), channel(a.a, b.a))
+}
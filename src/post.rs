@@ -0,0 +1,436 @@
+use std::fs::File;
+use std::io::Read;
+use std::thread;
+
+use {Color, RenderDestination};
+
+/// 3D color lookup table with `size`³ entries, sampled with trilinear filtering.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Lut3d {
+    size: usize,
+    // red changes fastest, then green, then blue
+    data: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// A LUT which maps every color to itself.
+    pub fn identity(size: usize) -> Lut3d {
+        Lut3d::from_fn(size, |col| col)
+    }
+
+    /// Builds the table by calling `f` for the color of every entry.
+    pub fn from_fn<F>(size: usize, f: F) -> Lut3d
+        where F: Fn(Color) -> Color {
+        assert!(size >= 2, "LUT size must be at least 2");
+        let step = |i: usize| (i * 255 / (size - 1)) as u8;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0 .. size {
+            for g in 0 .. size {
+                for r in 0 .. size {
+                    let col = f(Color::rgb(step(r), step(g), step(b)));
+                    data.push([col.r as f32 / 255f32, col.g as f32 / 255f32, col.b as f32 / 255f32]);
+                }
+            }
+        }
+        Lut3d {
+            size: size,
+            data: data,
+        }
+    }
+
+    /// Loads an Adobe/Resolve .cube file. Only 3D LUTs with the default 0..1 domain are supported.
+    pub fn load_cube(filename: &str) -> Result<Lut3d, String> {
+        let mut text = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        Lut3d::from_cube_str(&text).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    /// Parses the contents of a .cube file, see `load_cube`.
+    pub fn from_cube_str(text: &str) -> Result<Lut3d, String> {
+        let mut size = 0;
+        let mut data = vec![];
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("TITLE") || line.starts_with("DOMAIN_") {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("LUT_3D_SIZE") {
+                size = value.trim().parse::<usize>()
+                    .map_err(|_| format!("bad LUT_3D_SIZE in line {}", line_num + 1))?;
+                continue;
+            }
+            if line.starts_with("LUT_1D_SIZE") {
+                return Err("1D LUTs are not supported".to_string());
+            }
+            let nums = line.split_whitespace().map(|n| n.parse::<f32>()).collect::<Result<Vec<f32>, _>>();
+            match nums {
+                Ok(ref nums) if nums.len() == 3 => data.push([nums[0], nums[1], nums[2]]),
+                _ => return Err(format!("bad entry in line {}", line_num + 1)),
+            }
+        }
+        let expected = size.checked_mul(size).and_then(|n| n.checked_mul(size));
+        if size < 2 || expected != Some(data.len()) {
+            return Err(format!("expected {}³ entries, found {}", size, data.len()));
+        }
+        Ok(Lut3d {
+            size: size,
+            data: data,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Channels are 0..1.
+    fn sample(&self, r: f32, g: f32, b: f32) -> [f32; 3] {
+        let max = (self.size - 1) as f32;
+        let split = |c: f32| {
+            let c = c * max;
+            let i = (c as usize).min(self.size - 2);
+            (i, c - i as f32)
+        };
+        let (r0, rt) = split(r);
+        let (g0, gt) = split(g);
+        let (b0, bt) = split(b);
+        let at = |r: usize, g: usize, b: usize| self.data[(b * self.size + g) * self.size + r];
+        let mut out = [0f32; 3];
+        for (i, o) in out.iter_mut().enumerate() {
+            let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+            let c00 = lerp(at(r0, g0, b0)[i], at(r0 + 1, g0, b0)[i], rt);
+            let c10 = lerp(at(r0, g0 + 1, b0)[i], at(r0 + 1, g0 + 1, b0)[i], rt);
+            let c01 = lerp(at(r0, g0, b0 + 1)[i], at(r0 + 1, g0, b0 + 1)[i], rt);
+            let c11 = lerp(at(r0, g0 + 1, b0 + 1)[i], at(r0 + 1, g0 + 1, b0 + 1)[i], rt);
+            *o = lerp(lerp(c00, c10, gt), lerp(c01, c11, gt), bt);
+        }
+        out
+    }
+}
+
+/// One screen-space effect. The passes of a `PostChain` run in order.
+#[derive(PartialEq, Clone, Debug)]
+pub enum PostPass {
+    /// `brightness` is added (-1..1), `contrast` scales around the middle gray
+    /// (1 is unchanged), `gamma` > 1 brightens the dark tones.
+    ColorAdjust {brightness: f32, contrast: f32, gamma: f32},
+    /// Color grading
+    Lut(Lut3d),
+    /// Darkens the corners. `radius` is where the darkening starts, as a
+    /// fraction of the center-corner distance.
+    Vignette {strength: f32, radius: f32},
+    /// Adds `col` * `amount`, e.g. for a muzzle flash or damage indicator.
+    Flash {col: Color, amount: f32},
+    /// Blends towards `col`, `amount` is 0..1 (1 is fully faded).
+    Fade {col: Color, amount: f32},
+    /// Ordered (4x4 Bayer) dithering down to `levels` levels per channel.
+    Dither {levels: u32},
+    /// Darkens every `period`-th row by `darkness` (0..1).
+    Scanlines {period: u32, darkness: f32},
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5]];
+
+/// A pass prepared for a run: per-channel lookup tables are built only once.
+enum Stage<'p> {
+    Table(Box<[u8; 256]>),
+    Pass(&'p PostPass),
+}
+
+/// Ordered list of post-processing passes applied to a `RenderDestination`
+/// after `opticast` and the overlays. Pixels are 0xAARRGGBB, the alpha byte
+/// is kept as is.
+#[derive(Clone, Debug)]
+pub struct PostChain {
+    pub passes: Vec<PostPass>,
+    /// Filter used by `run_upscaled`
+    pub filter: Filter,
+    /// Number of threads the rows are split between; 1 runs on the calling thread.
+    pub threads: usize,
+}
+
+impl PostChain {
+    pub fn new() -> PostChain {
+        PostChain {
+            passes: vec![],
+            filter: Filter::Nearest,
+            threads: 1,
+        }
+    }
+
+    pub fn pass(mut self, pass: PostPass) -> Self {self.passes.push(pass); self }
+    pub fn filter(mut self, filter: Filter) -> Self {self.filter = filter; self }
+    pub fn threads(mut self, threads: usize) -> Self {self.threads = threads; self }
+
+    /// Applies the passes in place.
+    pub fn run(&self, dst: &mut RenderDestination) {
+        let (width, height) = (dst.width, dst.height);
+        let stages = self.stages();
+        let mut rows = rows_mut(dst);
+        self.for_each_chunk(&mut rows, |first_y, rows| {
+            for (i, row) in rows.iter_mut().enumerate() {
+                apply_stages(&stages, row, first_y + i as u32, width, height);
+            }
+        });
+    }
+
+    /// Scales the low-resolution `src` to the size of `dst` with `filter`,
+    /// then applies the passes on `dst`.
+    pub fn run_upscaled(&self, src: &RenderDestination, dst: &mut RenderDestination) {
+        let (width, height) = (dst.width, dst.height);
+        let src_rows = rows(src);
+        let src_width = src.width;
+        let filter = self.filter;
+        let stages = self.stages();
+        let mut rows = rows_mut(dst);
+        self.for_each_chunk(&mut rows, |first_y, rows| {
+            for (i, row) in rows.iter_mut().enumerate() {
+                let y = first_y + i as u32;
+                scale_row(&src_rows, src_width, filter, row, y, height);
+                apply_stages(&stages, row, y, width, height);
+            }
+        });
+    }
+
+    fn stages(&self) -> Vec<Stage<'_>> {
+        self.passes.iter().map(|pass| match *pass {
+            PostPass::ColorAdjust {brightness, contrast, gamma} => {
+                let mut table = Box::new([0u8; 256]);
+                for (i, t) in table.iter_mut().enumerate() {
+                    let c = ((i as f32 / 255f32 - 0.5f32) * contrast + 0.5f32 + brightness).clamp(0f32, 1f32);
+                    *t = (c.powf(1f32 / gamma) * 255f32).round() as u8;
+                }
+                Stage::Table(table)
+            },
+            ref pass => Stage::Pass(pass),
+        }).collect()
+    }
+
+    fn for_each_chunk<F>(&self, rows: &mut [&mut [u32]], f: F)
+        where F: Fn(u32, &mut [&mut [u32]]) + Sync {
+        let threads = self.threads.max(1);
+        if threads == 1 || rows.len() < 2 {
+            f(0, rows);
+            return;
+        }
+        // usize::div_ceil needs Rust 1.73
+        #[allow(clippy::manual_div_ceil)]
+        let chunk_size = (rows.len() + threads - 1) / threads;
+        let f = &f;
+        thread::scope(|scope| {
+            for (i, chunk) in rows.chunks_mut(chunk_size).enumerate() {
+                scope.spawn(move || f((i * chunk_size) as u32, chunk));
+            }
+        });
+    }
+}
+
+impl Default for PostChain {
+    fn default() -> PostChain {
+        PostChain::new()
+    }
+}
+
+// One slice per row, the pitch of the destination is respected
+fn rows_mut(dst: &mut RenderDestination) -> Vec<&mut [u32]> {
+    assert!(dst.bytes_per_line >= dst.width * 4, "bytes_per_line is smaller than the width");
+    let (width, height, bytes_per_line) = (dst.width, dst.height, dst.bytes_per_line);
+    let ptr = dst.as_mut_ptr();
+    (0 .. height).map(|y| unsafe {
+        std::slice::from_raw_parts_mut(ptr.offset((y * bytes_per_line) as isize) as *mut u32, width as usize)
+    }).collect()
+}
+
+fn rows(src: &RenderDestination) -> Vec<&[u32]> {
+    assert!(src.bytes_per_line >= src.width * 4, "bytes_per_line is smaller than the width");
+    let ptr = src.as_ptr();
+    (0 .. src.height).map(|y| unsafe {
+        std::slice::from_raw_parts(ptr.offset((y * src.bytes_per_line) as isize) as *const u32, src.width as usize)
+    }).collect()
+}
+
+fn channels(pixel: u32) -> [f32; 3] {
+    [((pixel >> 16) & 0xFF) as f32, ((pixel >> 8) & 0xFF) as f32, (pixel & 0xFF) as f32]
+}
+
+fn with_channels(pixel: u32, c: [f32; 3]) -> u32 {
+    let to_byte = |v: f32| v.round().clamp(0f32, 255f32) as u32;
+    (pixel & 0xFF00_0000) | (to_byte(c[0]) << 16) | (to_byte(c[1]) << 8) | to_byte(c[2])
+}
+
+fn scale_row(src_rows: &[&[u32]], src_width: u32, filter: Filter, row: &mut [u32], y: u32, height: u32) {
+    let src_height = src_rows.len() as u32;
+    let width = row.len() as u32;
+    if src_width == 0 || src_height == 0 {
+        return;
+    }
+    match filter {
+        Filter::Nearest => {
+            let src_row = src_rows[(y * src_height / height) as usize];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = src_row[x * src_width as usize / width as usize];
+            }
+        },
+        Filter::Bilinear => {
+            // Pixel centers are mapped onto each other
+            let map = |i: u32, from: u32, to: u32| {
+                let p = ((i as f32 + 0.5f32) * from as f32 / to as f32 - 0.5f32).clamp(0f32, (from - 1) as f32);
+                let i0 = p as usize;
+                (i0, (i0 + 1).min(from as usize - 1), p - i0 as f32)
+            };
+            let (y0, y1, ty) = map(y, src_height, height);
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let (x0, x1, tx) = map(x as u32, src_width, width);
+                let (c00, c10) = (channels(src_rows[y0][x0]), channels(src_rows[y0][x1]));
+                let (c01, c11) = (channels(src_rows[y1][x0]), channels(src_rows[y1][x1]));
+                let mut c = [0f32; 3];
+                for i in 0 .. 3 {
+                    let top = c00[i] + (c10[i] - c00[i]) * tx;
+                    let bottom = c01[i] + (c11[i] - c01[i]) * tx;
+                    c[i] = top + (bottom - top) * ty;
+                }
+                *pixel = with_channels(src_rows[y0][x0], c);
+            }
+        },
+    }
+}
+
+fn apply_stages(stages: &[Stage], row: &mut [u32], y: u32, width: u32, height: u32) {
+    for stage in stages.iter() {
+        match *stage {
+            Stage::Table(ref table) => for pixel in row.iter_mut() {
+                let c = *pixel;
+                *pixel = (c & 0xFF00_0000)
+                    | ((table[((c >> 16) & 0xFF) as usize] as u32) << 16)
+                    | ((table[((c >> 8) & 0xFF) as usize] as u32) << 8)
+                    | (table[(c & 0xFF) as usize] as u32);
+            },
+            Stage::Pass(pass) => apply_pass(pass, row, y, width, height),
+        }
+    }
+}
+
+fn apply_pass(pass: &PostPass, row: &mut [u32], y: u32, width: u32, height: u32) {
+    match *pass {
+        // Prepared into a table by `PostChain::stages`
+        PostPass::ColorAdjust {..} => {},
+        PostPass::Lut(ref lut) => for pixel in row.iter_mut() {
+            let c = channels(*pixel);
+            let out = lut.sample(c[0] / 255f32, c[1] / 255f32, c[2] / 255f32);
+            *pixel = with_channels(*pixel, [out[0] * 255f32, out[1] * 255f32, out[2] * 255f32]);
+        },
+        PostPass::Vignette {strength, radius} => {
+            let (cx, cy) = (width as f32 * 0.5f32, height as f32 * 0.5f32);
+            let max_dist = (cx * cx + cy * cy).sqrt();
+            let dy = y as f32 + 0.5f32 - cy;
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let dx = x as f32 + 0.5f32 - cx;
+                let dist = (dx * dx + dy * dy).sqrt() / max_dist;
+                let t = ((dist - radius) / (1f32 - radius).max(0.0001f32)).clamp(0f32, 1f32);
+                let factor = 1f32 - strength * t * t * (3f32 - 2f32 * t);
+                let c = channels(*pixel);
+                *pixel = with_channels(*pixel, [c[0] * factor, c[1] * factor, c[2] * factor]);
+            }
+        },
+        PostPass::Flash {col, amount} => {
+            let add = [col.r as f32 * amount, col.g as f32 * amount, col.b as f32 * amount];
+            for pixel in row.iter_mut() {
+                let c = channels(*pixel);
+                *pixel = with_channels(*pixel, [c[0] + add[0], c[1] + add[1], c[2] + add[2]]);
+            }
+        },
+        PostPass::Fade {col, amount} => {
+            let amount = amount.clamp(0f32, 1f32);
+            let target = [col.r as f32, col.g as f32, col.b as f32];
+            for pixel in row.iter_mut() {
+                let c = channels(*pixel);
+                *pixel = with_channels(*pixel, [
+                    c[0] + (target[0] - c[0]) * amount,
+                    c[1] + (target[1] - c[1]) * amount,
+                    c[2] + (target[2] - c[2]) * amount]);
+            }
+        },
+        PostPass::Dither {levels} => {
+            if levels < 2 {
+                return;
+            }
+            let step = 255f32 / (levels - 1) as f32;
+            let bayer_row = BAYER_4X4[(y % 4) as usize];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let threshold = (bayer_row[x % 4] as f32 + 0.5f32) / 16f32;
+                let c = channels(*pixel);
+                let quantize = |v: f32| ((v / step + threshold - 0.5f32).round() * step).clamp(0f32, 255f32);
+                *pixel = with_channels(*pixel, [quantize(c[0]), quantize(c[1]), quantize(c[2])]);
+            }
+        },
+        PostPass::Scanlines {period, darkness} => {
+            // u32::is_multiple_of needs Rust 1.87
+            #[allow(clippy::manual_is_multiple_of)]
+            if period == 0 || y % period != 0 {
+                return;
+            }
+            let factor = 1f32 - darkness.clamp(0f32, 1f32);
+            for pixel in row.iter_mut() {
+                let c = channels(*pixel);
+                *pixel = with_channels(*pixel, [c[0] * factor, c[1] * factor, c[2] * factor]);
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY_2: &str = "TITLE \"identity\"\n\
+        # comment\n\
+        LUT_3D_SIZE 2\n\
+        DOMAIN_MIN 0 0 0\n\
+        0 0 0\n1 0 0\n0 1 0\n1 1 0\n\
+        0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+
+    #[test]
+    fn parse_cube() {
+        assert_eq!(Lut3d::from_cube_str(IDENTITY_2), Ok(Lut3d::identity(2)));
+    }
+
+    #[test]
+    fn parse_empty_cube() {
+        assert_eq!(Lut3d::from_cube_str(""), Err("expected 0³ entries, found 0".to_string()));
+    }
+
+    #[test]
+    fn parse_cube_bad_lines() {
+        assert_eq!(Lut3d::from_cube_str("LUT_3D_SIZE two"), Err("bad LUT_3D_SIZE in line 1".to_string()));
+        assert_eq!(Lut3d::from_cube_str("LUT_3D_SIZE 2\n0 0"), Err("bad entry in line 2".to_string()));
+        assert_eq!(Lut3d::from_cube_str("LUT_1D_SIZE 16"), Err("1D LUTs are not supported".to_string()));
+    }
+
+    #[test]
+    fn parse_cube_entry_count() {
+        let missing_last = IDENTITY_2.trim_end().rsplit_once('\n').unwrap().0;
+        assert_eq!(Lut3d::from_cube_str(missing_last), Err("expected 2³ entries, found 7".to_string()));
+        assert!(Lut3d::from_cube_str("LUT_3D_SIZE 1\n0 0 0").is_err());
+        assert!(Lut3d::from_cube_str("LUT_3D_SIZE 18446744073709551615").is_err());
+    }
+
+    #[test]
+    fn sample_identity() {
+        let lut = Lut3d::identity(17);
+        for &(r, g, b) in [(0f32, 0f32, 0f32), (1f32, 1f32, 1f32), (0.25f32, 0.5f32, 0.75f32)].iter() {
+            let out = lut.sample(r, g, b);
+            assert!((out[0] - r).abs() < 0.01 && (out[1] - g).abs() < 0.01 && (out[2] - b).abs() < 0.01, "{:?}", out);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use {Color, LightingMode, SideShades, Voxlap};
+
+/// All the rendering quality knobs of the engine in one value.
+#[derive(PartialEq, Clone, Debug)]
+pub struct RenderSettings {
+    pub max_scan_dist: i32,
+    /// anginc, 1 is the best quality
+    pub raycast_density: i32,
+    pub mip_scan_dist: i32,
+    pub vxl_mip_use: i32,
+    pub kv6_mip_factor: i32,
+    /// None disables the fog
+    pub fog_color: Option<Color>,
+    pub kv6_color: Color,
+    pub xplane_min: i32,
+    pub xplane_max: i32,
+    pub lighting_mode: LightingMode,
+    pub side_shades: SideShades,
+}
+
+/// A setting which differs between two `RenderSettings`, with its config file
+/// name and both values.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SettingDiff {
+    pub name: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+impl RenderSettings {
+    pub fn low() -> RenderSettings {
+        RenderSettings {
+            max_scan_dist: 256,
+            raycast_density: 3,
+            mip_scan_dist: 64,
+            vxl_mip_use: 4,
+            kv6_mip_factor: 48,
+            ..RenderSettings::default()
+        }
+    }
+
+    pub fn medium() -> RenderSettings {
+        RenderSettings {
+            max_scan_dist: 512,
+            raycast_density: 2,
+            mip_scan_dist: 96,
+            vxl_mip_use: 3,
+            kv6_mip_factor: 72,
+            ..RenderSettings::default()
+        }
+    }
+
+    pub fn high() -> RenderSettings {
+        RenderSettings {
+            max_scan_dist: 1024,
+            raycast_density: 1,
+            mip_scan_dist: 128,
+            vxl_mip_use: 2,
+            kv6_mip_factor: 96,
+            ..RenderSettings::default()
+        }
+    }
+
+    pub fn ultra() -> RenderSettings {
+        RenderSettings {
+            max_scan_dist: 2047,
+            raycast_density: 1,
+            mip_scan_dist: 256,
+            vxl_mip_use: 1,
+            kv6_mip_factor: 192,
+            ..RenderSettings::default()
+        }
+    }
+
+    /// Reads the current settings back from the engine.
+    pub fn from_engine(vox: &Voxlap) -> RenderSettings {
+        let (xplane_min, xplane_max) = vox.get_xplane_range();
+        RenderSettings {
+            max_scan_dist: vox.get_max_scan_dist(),
+            raycast_density: vox.get_raycast_density(),
+            mip_scan_dist: vox.get_mip_scan_dist(),
+            vxl_mip_use: vox.get_vxl_mip_use(),
+            kv6_mip_factor: vox.get_kv6_mip_factor(),
+            fog_color: vox.get_fog_color(),
+            kv6_color: vox.get_kv6col(),
+            xplane_min: xplane_min,
+            xplane_max: xplane_max,
+            lighting_mode: vox.get_lighting_mode(),
+            side_shades: vox.get_side_shades(),
+        }
+    }
+
+    /// Sets every setting at once. Call it between frames, not between
+    /// `opticast` and the sprite drawing.
+    pub fn apply(&self, vox: &mut Voxlap) {
+        vox.set_max_scan_dist(self.max_scan_dist);
+        vox.set_raycast_density(self.raycast_density);
+        vox.set_mip_scan_dist(self.mip_scan_dist);
+        vox.set_vxl_mip_use(self.vxl_mip_use);
+        vox.set_kv6_mip_factor(self.kv6_mip_factor);
+        match self.fog_color {
+            Some(col) => vox.set_fog_color(col),
+            None => vox.disable_fog(),
+        }
+        vox.set_kv6col(self.kv6_color);
+        vox.set_xplane_range(self.xplane_min, self.xplane_max);
+        vox.set_lighting_mode(self.lighting_mode);
+        vox.set_side_shades(self.side_shades);
+    }
+
+    /// The settings in config file order, formatted as in the config file.
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let s = &self.side_shades;
+        vec![
+            ("max_scan_dist", self.max_scan_dist.to_string()),
+            ("raycast_density", self.raycast_density.to_string()),
+            ("mip_scan_dist", self.mip_scan_dist.to_string()),
+            ("vxl_mip_use", self.vxl_mip_use.to_string()),
+            ("kv6_mip_factor", self.kv6_mip_factor.to_string()),
+            ("fog_color", self.fog_color.map_or("none".to_string(), |c| format_color(&c))),
+            ("kv6_color", format_color(&self.kv6_color)),
+            ("xplane_min", self.xplane_min.to_string()),
+            ("xplane_max", self.xplane_max.to_string()),
+            ("lighting_mode", match self.lighting_mode {
+                LightingMode::NoSpecialLighting => "none",
+                LightingMode::SimpleEstimatedNormalLighting => "normals",
+                LightingMode::MultiplePointSourceLighting => "lights",
+            }.to_string()),
+            ("side_shades", format!("{} {} {} {} {} {}", s.z_min, s.z_max, s.x_min, s.x_max, s.y_min, s.y_max)),
+        ]
+    }
+
+    /// One `name = value` line per setting, colors are hex RRGGBB.
+    pub fn to_config_string(&self) -> String {
+        self.entries().iter().map(|(name, value)| format!("{} = {}\n", name, value)).collect()
+    }
+
+    /// Parses the output of `to_config_string`. Missing settings keep their
+    /// default value, lines starting with '#' are comments.
+    pub fn from_config_str(config: &str) -> Result<RenderSettings, String> {
+        let mut settings = RenderSettings::default();
+        for (line_num, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = match line.find('=') {
+                Some(i) => (line[.. i].trim(), line[i + 1 ..].trim()),
+                None => return Err(format!("line {}: expected 'name = value'", line_num + 1)),
+            };
+            let bad_value = || format!("line {}: bad value for {}: {}", line_num + 1, name, value);
+            let int = || value.parse::<i32>().map_err(|_| bad_value());
+            // The engine setters panic on values < 1
+            let positive_int = || int().and_then(|n| if n >= 1 {Ok(n)} else {Err(bad_value())});
+            match name {
+                "max_scan_dist" => settings.max_scan_dist = int()?,
+                "raycast_density" => settings.raycast_density = positive_int()?,
+                "mip_scan_dist" => settings.mip_scan_dist = int()?,
+                "vxl_mip_use" => settings.vxl_mip_use = positive_int()?,
+                "kv6_mip_factor" => settings.kv6_mip_factor = int()?,
+                "fog_color" => settings.fog_color = if value == "none" {
+                    None
+                } else {
+                    Some(parse_color(value).ok_or_else(bad_value)?)
+                },
+                "kv6_color" => settings.kv6_color = parse_color(value).ok_or_else(bad_value)?,
+                "xplane_min" => settings.xplane_min = int()?,
+                "xplane_max" => settings.xplane_max = int()?,
+                "lighting_mode" => settings.lighting_mode = match value {
+                    "none" => LightingMode::NoSpecialLighting,
+                    "normals" => LightingMode::SimpleEstimatedNormalLighting,
+                    "lights" => LightingMode::MultiplePointSourceLighting,
+                    _ => return Err(bad_value()),
+                },
+                "side_shades" => {
+                    let shades = value.split_whitespace().map(|n| n.parse::<i8>()).collect::<Result<Vec<i8>, _>>();
+                    settings.side_shades = match shades {
+                        Ok(ref s) if s.len() == 6 => SideShades::new(s[0], s[1], s[2], s[3], s[4], s[5]),
+                        _ => return Err(bad_value()),
+                    };
+                },
+                _ => return Err(format!("line {}: unknown setting: {}", line_num + 1, name)),
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        let mut file = File::create(filename)?;
+        file.write_all(self.to_config_string().as_bytes())
+    }
+
+    pub fn load(filename: &str) -> io::Result<RenderSettings> {
+        let mut config = String::new();
+        File::open(filename)?.read_to_string(&mut config)?;
+        RenderSettings::from_config_str(&config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", filename, e)))
+    }
+
+    /// The settings which are different in `other`.
+    pub fn diff(&self, other: &RenderSettings) -> Vec<SettingDiff> {
+        self.entries().into_iter().zip(other.entries())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((name, old), (_, new))| SettingDiff {
+                name: name,
+                old: old,
+                new: new,
+            })
+            .collect()
+    }
+}
+
+/// The engine defaults after `Voxlap::new`.
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            max_scan_dist: 1448,
+            raycast_density: 1,
+            mip_scan_dist: 128,
+            vxl_mip_use: 1,
+            kv6_mip_factor: 96,
+            fog_color: None,
+            kv6_color: Color::rgb(0x80, 0x80, 0x80),
+            xplane_min: 0,
+            xplane_max: i32::MAX,
+            lighting_mode: LightingMode::NoSpecialLighting,
+            side_shades: SideShades::off(),
+        }
+    }
+}
+
+fn format_color(col: &Color) -> String {
+    format!("{:02X}{:02X}{:02X}", col.r, col.g, col.b)
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    if value.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(value, 16).ok().map(|rgb| Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_round_trip() {
+        for settings in [RenderSettings::low(), RenderSettings::ultra(), RenderSettings {
+            fog_color: Some(Color::rgb(0x12, 0xAB, 0xEF)),
+            lighting_mode: LightingMode::MultiplePointSourceLighting,
+            side_shades: SideShades::new(-1, 2, -3, 4, -5, 6),
+            ..RenderSettings::default()
+        }].iter() {
+            assert_eq!(RenderSettings::from_config_str(&settings.to_config_string()).as_ref(), Ok(settings));
+        }
+    }
+
+    #[test]
+    fn empty_config_is_default() {
+        assert_eq!(RenderSettings::from_config_str(""), Ok(RenderSettings::default()));
+        assert_eq!(RenderSettings::from_config_str("# comment\n\n   \n"), Ok(RenderSettings::default()));
+    }
+
+    #[test]
+    fn config_bad_lines() {
+        assert_eq!(RenderSettings::from_config_str("max_scan_dist 10"), Err("line 1: expected 'name = value'".to_string()));
+        assert_eq!(RenderSettings::from_config_str("\nfoo = 1"), Err("line 2: unknown setting: foo".to_string()));
+        assert_eq!(RenderSettings::from_config_str("mip_scan_dist = far"), Err("line 1: bad value for mip_scan_dist: far".to_string()));
+        assert!(RenderSettings::from_config_str("fog_color = 12345").is_err());
+        assert!(RenderSettings::from_config_str("lighting_mode = sun").is_err());
+        assert!(RenderSettings::from_config_str("side_shades = 1 2 3 4 5").is_err());
+    }
+
+    #[test]
+    fn config_rejects_values_the_engine_panics_on() {
+        assert_eq!(RenderSettings::from_config_str("raycast_density = 1").map(|s| s.raycast_density), Ok(1));
+        assert!(RenderSettings::from_config_str("raycast_density = 0").is_err());
+        assert_eq!(RenderSettings::from_config_str("vxl_mip_use = 1").map(|s| s.vxl_mip_use), Ok(1));
+        assert!(RenderSettings::from_config_str("vxl_mip_use = -2").is_err());
+    }
+
+    #[test]
+    fn diff_lists_changed_settings() {
+        assert!(RenderSettings::high().diff(&RenderSettings::high()).is_empty());
+        let diff = RenderSettings::default().diff(&RenderSettings {fog_color: Some(Color::rgb(1, 2, 3)), ..RenderSettings::default()});
+        assert_eq!(diff, vec![SettingDiff {name: "fog_color", old: "none".to_string(), new: "010203".to_string()}]);
+    }
+}
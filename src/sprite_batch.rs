@@ -0,0 +1,206 @@
+use std::cmp::Ordering;
+
+use c_api;
+use {vec3, Orientation, RenderContext, Sprite};
+
+/// View frustum matching the projection `RenderContext::set_camera_with_center` gives to the engine.
+pub struct Frustum {
+    ori: Orientation,
+    // Tangents of the angles between the forward vector and the 4 sides
+    tan_left: f32,
+    tan_right: f32,
+    tan_top: f32,
+    tan_bottom: f32,
+}
+
+impl Frustum {
+    /// The frustum of `set_camera`, with the projection center in the middle of the screen.
+    pub fn new(ori: &Orientation, focal_length: f32, width: u32, height: u32) -> Frustum {
+        Frustum::with_center(ori, focal_length, width, height, width as f32 * 0.5f32, height as f32 * 0.5f32)
+    }
+
+    pub fn with_center(ori: &Orientation, focal_length: f32, width: u32, height: u32, center_x: f32, center_y: f32) -> Frustum {
+        // set_camera_with_center uses dahz = width * 0.5 * focal_length
+        let dahz = width as f32 * 0.5f32 * focal_length;
+        Frustum {
+            ori: ori.clone(),
+            tan_left: center_x / dahz,
+            tan_right: (width as f32 - center_x) / dahz,
+            tan_top: center_y / dahz,
+            tan_bottom: (height as f32 - center_y) / dahz,
+        }
+    }
+
+    /// Position in camera space: x is right, y is down, z is the distance along the view direction.
+    pub fn to_camera_space(&self, pos: &vec3) -> vec3 {
+        let d = *pos - self.ori.pos;
+        vec3::new(d.dot(&self.ori.right_vec), d.dot(&self.ori.down_vec), d.dot(&self.ori.forward_vec))
+    }
+
+    pub fn contains_sphere(&self, center: &vec3, radius: f32) -> bool {
+        let p = self.to_camera_space(center);
+        if p.z < -radius {
+            return false;
+        }
+        // Signed distance from a side plane going through the camera position
+        let outside = |offset: f32, tan: f32| (offset - p.z * tan) / (1f32 + tan * tan).sqrt() > radius;
+        !(outside(-p.x, self.tan_left) || outside(p.x, self.tan_right) ||
+          outside(-p.y, self.tan_top) || outside(p.y, self.tan_bottom))
+    }
+}
+
+pub struct SpriteBatchStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+/// Collects sprites for a frame, then draws the ones inside the view frustum
+/// front-to-back.
+pub struct SpriteBatch<'a> {
+    sprites: Vec<(&'a Sprite, f32)>,
+    mip_distance: Option<f32>,
+}
+
+impl<'a> SpriteBatch<'a> {
+    pub fn new() -> SpriteBatch<'a> {
+        SpriteBatch {
+            sprites: vec![],
+            mip_distance: None,
+        }
+    }
+
+    /// `radius` is the radius of a sphere around the sprite position which contains the whole sprite.
+    pub fn add(&mut self, spr: &'a Sprite, radius: f32) {
+        self.sprites.push((spr, radius));
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    /// Distance where full KV6 detail ends. Every doubling of the distance
+    /// after it uses the next lower MIP level (see `Sprite::generate_mipmaps`).
+    /// None always draws full detail.
+    pub fn set_mip_distance(&mut self, dist: Option<f32>) {
+        self.mip_distance = dist;
+    }
+
+    /// Without a `set_camera` call on the context there is no frustum to cull
+    /// with, so every sprite is drawn in the order of `add` with full detail.
+    pub fn draw(&self, render_context: &RenderContext) -> SpriteBatchStats {
+        let (ori, focal_length, (center_x, center_y)) = match render_context.current_camera() {
+            Some(camera) => camera,
+            None => {
+                for &(spr, _) in self.sprites.iter() {
+                    render_context.draw_sprite(spr);
+                }
+                return SpriteBatchStats {
+                    drawn: self.sprites.len() as u32,
+                    culled: 0,
+                };
+            },
+        };
+        let (width, height) = render_context.size();
+        let frustum = Frustum::with_center(&ori, focal_length, width, height, center_x, center_y);
+
+        let mut visible = self.sprites.iter()
+            .filter(|&&(spr, radius)| frustum.contains_sphere(&spr.get_pos(), radius))
+            .map(|&(spr, _)| (spr, frustum.to_camera_space(&spr.get_pos()).z))
+            .collect::<Vec<_>>();
+        visible.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+
+        for &(spr, depth) in visible.iter() {
+            let level = match self.mip_distance {
+                Some(mip_distance) if depth > mip_distance => (depth / mip_distance).log2() as u32 + 1,
+                _ => 0,
+            };
+            draw_sprite_mip(render_context, spr, level);
+        }
+        SpriteBatchStats {
+            drawn: visible.len() as u32,
+            culled: (self.sprites.len() - visible.len()) as u32,
+        }
+    }
+}
+
+impl<'a> Default for SpriteBatch<'a> {
+    fn default() -> SpriteBatch<'a> {
+        SpriteBatch::new()
+    }
+}
+
+fn draw_sprite_mip(render_context: &RenderContext, spr: &Sprite, level: u32) {
+    let level = level.min(spr.mip_level_count() - 1);
+    if level == 0 {
+        render_context.draw_sprite(spr);
+        return;
+    }
+    unsafe {
+        let mut kv6: *const c_api::kv6data = spr.ptr.voxnum;
+        for _ in 0 .. level {
+            kv6 = (*kv6).lowermip;
+        }
+        // Every MIP level halves the resolution, so the axis vectors have to grow to keep the size
+        let scale = (1 << level) as f32;
+        let scaled = |p: c_api::point3d| c_api::point3d {x: p.x * scale, y: p.y * scale, z: p.z * scale};
+        let mip = c_api::vx5sprite {
+            pos: spr.ptr.pos,
+            flags: spr.ptr.flags,
+            s: scaled(spr.ptr.s),
+            voxnum: kv6 as *mut c_api::kv6data,
+            h: scaled(spr.ptr.h),
+            kfatim: spr.ptr.kfatim,
+            f: scaled(spr.ptr.f),
+            okfatim: spr.ptr.okfatim,
+        };
+        c_api::drawsprite(&mip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Looks along +x, right is +y, down is +z
+    fn camera() -> Orientation {
+        Orientation::from_axes(&vec3::new(0f32, 0f32, 0f32),
+            &vec3::new(0f32, 1f32, 0f32), &vec3::new(0f32, 0f32, 1f32), &vec3::new(1f32, 0f32, 0f32))
+    }
+
+    #[test]
+    fn centered_frustum() {
+        // dahz = 50, so the sides are at 45 degrees
+        let frustum = Frustum::new(&camera(), 1f32, 100, 100);
+        assert!(frustum.contains_sphere(&vec3::new(10f32, 0f32, 0f32), 0.1f32));
+        assert!(frustum.contains_sphere(&vec3::new(10f32, 9.5f32, -9.5f32), 0.1f32));
+        assert!(!frustum.contains_sphere(&vec3::new(10f32, 11f32, 0f32), 0.1f32));
+        assert!(!frustum.contains_sphere(&vec3::new(10f32, 0f32, -11f32), 0.1f32));
+        assert!(!frustum.contains_sphere(&vec3::new(-10f32, 0f32, 0f32), 0.1f32));
+        // Behind the camera, but big enough to reach into the view
+        assert!(frustum.contains_sphere(&vec3::new(-1f32, 0f32, 0f32), 2f32));
+    }
+
+    #[test]
+    fn off_center_frustum() {
+        // The projection center is on the left edge and 1/4 from the top
+        let frustum = Frustum::with_center(&camera(), 1f32, 100, 100, 0f32, 25f32);
+        // Right: up to 100 / 50 * distance
+        assert!(frustum.contains_sphere(&vec3::new(10f32, 15f32, 0f32), 0.1f32));
+        assert!(!frustum.contains_sphere(&vec3::new(10f32, 21f32, 0f32), 0.1f32));
+        // Left: nothing left of the forward vector
+        assert!(!frustum.contains_sphere(&vec3::new(10f32, -1f32, 0f32), 0.1f32));
+        // Top: up to 25 / 50 * distance, bottom: up to 75 / 50 * distance
+        assert!(frustum.contains_sphere(&vec3::new(10f32, 1f32, -4.5f32), 0.1f32));
+        assert!(!frustum.contains_sphere(&vec3::new(10f32, 1f32, -6f32), 0.1f32));
+        assert!(frustum.contains_sphere(&vec3::new(10f32, 1f32, 14.5f32), 0.1f32));
+        assert!(!frustum.contains_sphere(&vec3::new(10f32, 1f32, 16f32), 0.1f32));
+    }
+}
@@ -0,0 +1,378 @@
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use {Color, RenderContext};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn right(&self) -> i32 {self.x + self.width}
+    pub fn bottom(&self) -> i32 {self.y + self.height}
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.right() && y < self.bottom()
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = cmp::max(self.x, other.x);
+        let y = cmp::max(self.y, other.y);
+        Rect::new(x, y,
+            cmp::max(cmp::min(self.right(), other.right()) - x, 0),
+            cmp::max(cmp::min(self.bottom(), other.bottom()) - y, 0))
+    }
+
+    /// The rect shrunk by `amount` pixels on every side.
+    pub fn shrink(&self, amount: i32) -> Rect {
+        Rect::new(self.x + amount, self.y + amount,
+            cmp::max(self.width - 2 * amount, 0), cmp::max(self.height - 2 * amount, 0))
+    }
+}
+
+/// The built-in engine fonts.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Font {
+    Font4x6,
+    Font6x8,
+}
+
+impl Font {
+    pub fn glyph_width(&self) -> i32 {
+        match *self {
+            Font::Font4x6 => 4,
+            Font::Font6x8 => 6,
+        }
+    }
+
+    pub fn glyph_height(&self) -> i32 {
+        match *self {
+            Font::Font4x6 => 6,
+            Font::Font6x8 => 8,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct MouseInput {
+    pub x: i32,
+    pub y: i32,
+    pub left_down: bool,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ButtonState {
+    Normal,
+    Hovered,
+    Pressed,
+}
+
+/// Colors used by the widgets.
+#[derive(Clone, Debug)]
+pub struct Style {
+    pub panel: Color,
+    pub border: Color,
+    pub text: Color,
+    pub button: Color,
+    pub button_hovered: Color,
+    pub button_pressed: Color,
+    pub bar_fill: Color,
+    pub bar_background: Color,
+    pub font: Font,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            panel: Color::rgb(40, 40, 48),
+            border: Color::rgb(120, 120, 140),
+            text: Color::white(),
+            button: Color::rgb(70, 70, 90),
+            button_hovered: Color::rgb(90, 90, 120),
+            button_pressed: Color::rgb(50, 50, 70),
+            bar_fill: Color::dark_green(),
+            bar_background: Color::black(),
+            font: Font::Font6x8,
+        }
+    }
+}
+
+/// The part of the UI which has to live between frames: which widget holds
+/// the mouse button and the mouse state of the previous frame.
+#[derive(Default)]
+pub struct UiState {
+    active: Option<u64>,
+    prev_mouse: MouseInput,
+}
+
+impl UiState {
+    pub fn new() -> UiState {
+        UiState::default()
+    }
+}
+
+/// Immediate-mode UI for one frame. Create it after `opticast` and the 3D
+/// drawing, call the widget functions, then drop it.
+pub struct Ui<'r, 'a: 'r> {
+    render_context: &'r RenderContext<'a>,
+    state: &'r mut UiState,
+    mouse: MouseInput,
+    clip_stack: Vec<Rect>,
+    pub style: Style,
+}
+
+impl<'r, 'a> Ui<'r, 'a> {
+    pub fn new(render_context: &'r RenderContext<'a>, state: &'r mut UiState, mouse: MouseInput) -> Ui<'r, 'a> {
+        let (width, height) = render_context.size();
+        Ui {
+            render_context: render_context,
+            state: state,
+            mouse: mouse,
+            clip_stack: vec![Rect::new(0, 0, width as i32, height as i32)],
+            style: Style::default(),
+        }
+    }
+
+    fn clip(&self) -> Rect {
+        self.clip_stack[self.clip_stack.len() - 1]
+    }
+
+    /// Restricts drawing to `rect` (intersected with the current clip rect) until `pop_clip`.
+    pub fn push_clip(&mut self, rect: &Rect) {
+        let clip = self.clip().intersect(rect);
+        self.clip_stack.push(clip);
+    }
+
+    pub fn pop_clip(&mut self) {
+        assert!(self.clip_stack.len() > 1, "pop_clip without push_clip");
+        self.clip_stack.pop();
+    }
+
+    pub fn fill_rect(&self, rect: &Rect, col: Color) {
+        let r = rect.intersect(&self.clip());
+        if r.is_empty() {
+            return;
+        }
+        for y in r.y .. r.bottom() {
+            self.render_context.draw_line_2d_clipped(r.x as f32, y as f32, (r.right() - 1) as f32, y as f32, col);
+        }
+    }
+
+    pub fn outline_rect(&self, rect: &Rect, col: Color) {
+        if rect.is_empty() {
+            return;
+        }
+        self.fill_rect(&Rect::new(rect.x, rect.y, rect.width, 1), col);
+        self.fill_rect(&Rect::new(rect.x, rect.bottom() - 1, rect.width, 1), col);
+        self.fill_rect(&Rect::new(rect.x, rect.y, 1, rect.height), col);
+        self.fill_rect(&Rect::new(rect.right() - 1, rect.y, 1, rect.height), col);
+    }
+
+    /// Draws a framed panel with an optional title bar and returns the rect of
+    /// its content area.
+    pub fn panel(&self, rect: &Rect, title: Option<&str>) -> Rect {
+        self.fill_rect(rect, self.style.panel);
+        self.outline_rect(rect, self.style.border);
+        match title {
+            None => rect.shrink(2),
+            Some(title) => {
+                let bar_height = self.style.font.glyph_height() + 4;
+                let bar = Rect::new(rect.x, rect.y, rect.width, bar_height);
+                self.fill_rect(&bar, self.style.border);
+                let font = self.style.font;
+                let col = self.style.text;
+                self.text(&bar.shrink(2), title, font, Align::Left, col);
+                Rect::new(rect.x + 2, rect.y + bar_height + 2, rect.width - 4, rect.height - bar_height - 4)
+            }
+        }
+    }
+
+    /// `fraction` is clamped to 0..1.
+    pub fn progress_bar(&self, rect: &Rect, fraction: f32) {
+        let fraction = fraction.clamp(0f32, 1f32);
+        self.fill_rect(rect, self.style.bar_background);
+        let inner = rect.shrink(1);
+        let filled = Rect::new(inner.x, inner.y, (inner.width as f32 * fraction).round() as i32, inner.height);
+        self.fill_rect(&filled, self.style.bar_fill);
+        self.outline_rect(rect, self.style.border);
+    }
+
+    /// Returns the state of the button in this frame and whether it was clicked
+    /// (mouse button released above it after pressing it). `id` has to be unique
+    /// among the buttons of the UI.
+    pub fn button(&mut self, id: &str, rect: &Rect, label: &str) -> (ButtonState, bool) {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        let id = hasher.finish();
+
+        let hovered = rect.contains(self.mouse.x, self.mouse.y) && self.clip().contains(self.mouse.x, self.mouse.y);
+        let just_pressed = self.mouse.left_down && !self.state.prev_mouse.left_down;
+        let mut clicked = false;
+        if hovered && just_pressed {
+            self.state.active = Some(id);
+        }
+        if !self.mouse.left_down && self.state.active == Some(id) {
+            clicked = hovered;
+            self.state.active = None;
+        }
+        let state = if self.state.active == Some(id) && hovered {
+            ButtonState::Pressed
+        } else if hovered {
+            ButtonState::Hovered
+        } else {
+            ButtonState::Normal
+        };
+
+        let col = match state {
+            ButtonState::Normal => self.style.button,
+            ButtonState::Hovered => self.style.button_hovered,
+            ButtonState::Pressed => self.style.button_pressed,
+        };
+        self.fill_rect(rect, col);
+        self.outline_rect(rect, self.style.border);
+        let font = self.style.font;
+        let text_col = self.style.text;
+        let text_y = rect.y + (rect.height - font.glyph_height()) / 2;
+        self.text(&Rect::new(rect.x, text_y, rect.width, font.glyph_height()), label, font, Align::Center, text_col);
+        (state, clicked)
+    }
+
+    /// Draws word-wrapped text inside `rect`. Explicit '\n'-s start a new line.
+    /// Returns the height of the text in pixels.
+    pub fn text(&self, rect: &Rect, text: &str, font: Font, align: Align, col: Color) -> i32 {
+        let max_chars = cmp::max(rect.width / font.glyph_width(), 1) as usize;
+        let lines = wrap_text(text, max_chars);
+        let clip = self.clip().intersect(rect);
+        for (i, line) in lines.iter().enumerate() {
+            let y = rect.y + i as i32 * font.glyph_height();
+            if y < clip.y || y + font.glyph_height() > clip.bottom() {
+                continue;
+            }
+            let line_width = line.chars().count() as i32 * font.glyph_width();
+            let x = match align {
+                Align::Left => rect.x,
+                Align::Center => rect.x + (rect.width - line_width) / 2,
+                Align::Right => rect.right() - line_width,
+            };
+            // Only whole glyphs are drawn, the ones crossing the clip rect are dropped
+            let visible = line.chars().enumerate()
+                .filter(|&(j, _)| {
+                    let glyph_x = x + j as i32 * font.glyph_width();
+                    glyph_x >= clip.x && glyph_x + font.glyph_width() <= clip.right()
+                })
+                .collect::<Vec<_>>();
+            if visible.is_empty() {
+                continue;
+            }
+            let first = visible[0].0;
+            let shown = visible.iter().map(|&(_, c)| c).collect::<String>();
+            let glyph_x = (x + first as i32 * font.glyph_width()) as u32;
+            match font {
+                // -1 is the transparent background for the engine's font functions
+                Font::Font4x6 => self.render_context.print4x6(glyph_x, y as u32, col, Color::rgba(255, 255, 255, 255), &shown),
+                Font::Font6x8 => self.render_context.print6x8(glyph_x, y as u32, col, None, &shown),
+            }
+        }
+        lines.len() as i32 * font.glyph_height()
+    }
+}
+
+impl<'r, 'a> Drop for Ui<'r, 'a> {
+    fn drop(&mut self) {
+        self.state.prev_mouse = self.mouse;
+    }
+}
+
+/// Breaks the text into lines of at most `max_chars` characters (at least 1),
+/// at spaces if possible.
+pub fn wrap_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let line_len = line.chars().count();
+            let word_len = word.chars().count();
+            if line_len > 0 && line_len + 1 + word_len > max_chars {
+                lines.push(line);
+                line = String::new();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            let mut rest = word.chars().collect::<Vec<_>>();
+            // Words longer than a line are split
+            while line.chars().count() + rest.len() > max_chars {
+                let take = max_chars - line.chars().count();
+                line.extend(rest.drain(.. take));
+                lines.push(line);
+                line = String::new();
+            }
+            line.extend(rest);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_empty_text() {
+        assert_eq!(wrap_text("", 10), vec![""]);
+    }
+
+    #[test]
+    fn wrap_at_exact_line_length() {
+        assert_eq!(wrap_text("aaa bbb", 7), vec!["aaa bbb"]);
+        assert_eq!(wrap_text("aaa bbb", 6), vec!["aaa", "bbb"]);
+    }
+
+    #[test]
+    fn wrap_splits_long_words() {
+        assert_eq!(wrap_text("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(wrap_text("a bcdef", 3), vec!["a", "bcd", "ef"]);
+    }
+
+    #[test]
+    fn wrap_keeps_explicit_newlines() {
+        assert_eq!(wrap_text("a\n\nb", 10), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn wrap_counts_chars_not_bytes() {
+        assert_eq!(wrap_text("áéí óúü", 3), vec!["áéí", "óúü"]);
+    }
+
+    #[test]
+    fn wrap_with_zero_width_terminates() {
+        assert_eq!(wrap_text("ab", 0), vec!["a", "b"]);
+    }
+}
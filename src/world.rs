@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+use std::cmp;
+
+use c_api::vspans;
+use {ivec3, vec3, CsgOperationType, CubeFace, Voxel, VoxelColor, Voxlap};
+
+/// Number of voxels in a map column, same as the engine's MAXZDIM.
+pub const MAX_Z: i32 = 256;
+
+#[derive(Clone)]
+struct Column {
+    solid: [u64; 4],
+    colors: BTreeMap<u8, VoxelColor>,
+}
+
+impl Column {
+    fn new() -> Column {
+        Column {
+            solid: [0; 4],
+            colors: BTreeMap::new(),
+        }
+    }
+
+    fn is_solid(&self, z: i32) -> bool {
+        (self.solid[(z >> 6) as usize] >> (z & 63)) & 1 == 1
+    }
+
+    fn set_solid(&mut self, z: i32, solid: bool) {
+        if solid {
+            self.solid[(z >> 6) as usize] |= 1 << (z & 63);
+        } else {
+            self.solid[(z >> 6) as usize] &= !(1 << (z & 63));
+            self.colors.remove(&(z as u8));
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.solid.iter().all(|&bits| bits == 0)
+    }
+
+    /// Solid runs of the column as inclusive (z0, z1) pairs, top to bottom.
+    fn runs(&self) -> Vec<(i32, i32)> {
+        let mut runs = vec![];
+        let mut z = 0;
+        while z < MAX_Z {
+            if self.is_solid(z) {
+                let z0 = z;
+                while z < MAX_Z && self.is_solid(z) {
+                    z += 1;
+                }
+                runs.push((z0, z - 1));
+            } else {
+                z += 1;
+            }
+        }
+        runs
+    }
+}
+
+pub struct WorldHitScanResult {
+    pub hit_face: Option<CubeFace>,
+    pub pos: ivec3,
+    pub color: Option<VoxelColor>,
+}
+
+/// A voxel map held in Rust memory. Any number of worlds can exist at the same
+/// time; only the one passed to `activate` is visible to the engine.
+#[derive(Clone)]
+pub struct World {
+    width: i32,
+    height: i32,
+    columns: Vec<Column>,
+    cur_col: VoxelColor,
+}
+
+impl World {
+    /// Fails if the number of columns does not fit into an i32.
+    pub fn new(width: u32, height: u32) -> Result<World, String> {
+        let column_count = if width <= i32::MAX as u32 && height <= i32::MAX as u32 {
+            (width as i32).checked_mul(height as i32)
+        } else {
+            None
+        };
+        let column_count = column_count.ok_or_else(|| format!("world of {}x{} columns is too big", width, height))?;
+        Ok(World {
+            width: width as i32,
+            height: height as i32,
+            columns: vec![Column::new(); column_count as usize],
+            cur_col: VoxelColor::rgb(0x80, 0x80, 0x80),
+        })
+    }
+
+    /// Copies the map currently loaded into the engine. Reads every voxel
+    /// through the engine, so it is slow on big maps.
+    pub fn from_voxlap(vox: &Voxlap) -> World {
+        let size = vox.get_max_xy_dimension() as u32;
+        let mut world = World::new(size, size).expect("VSID * VSID columns fit into an i32");
+        for y in 0 .. world.height {
+            for x in 0 .. world.width {
+                let index = world.index(x, y);
+                let column = &mut world.columns[index];
+                for z in 0 .. MAX_Z {
+                    if vox.is_voxel_solid(&ivec3::new(x, y, z)) {
+                        column.set_solid(z, true);
+                        if let Some(col) = vox.get_cube(x, y, z) {
+                            column.colors.insert(z as u8, col);
+                        }
+                    }
+                }
+            }
+        }
+        world
+    }
+
+    pub fn width(&self) -> u32 {self.width as u32}
+    pub fn height(&self) -> u32 {self.height as u32}
+
+    /// Color given to voxels that get exposed by CSG edits, like `vx5.curcol`.
+    pub fn set_curcol(&mut self, col: VoxelColor) {
+        self.cur_col = col;
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    fn in_bounds(&self, x: i32, y: i32, z: i32) -> bool {
+        x >= 0 && y >= 0 && z >= 0 && x < self.width && y < self.height && z < MAX_Z
+    }
+
+    // --------------------------- VXL reading functions: ---------------------------
+
+    /// Like the engine, everything under the bottom of the map counts as solid.
+    pub fn is_voxel_solid(&self, pos: &ivec3) -> bool {
+        if pos.z >= MAX_Z {
+            return true;
+        }
+        self.in_bounds(pos.x, pos.y, pos.z) && self.columns[self.index(pos.x, pos.y)].is_solid(pos.z)
+    }
+
+    pub fn get_floor_z(&self, pos: &ivec3) -> i32 {
+        let mut z = cmp::max(pos.z, 0);
+        while z < MAX_Z && !self.is_voxel_solid(&ivec3::new(pos.x, pos.y, z)) {
+            z += 1;
+        }
+        z
+    }
+
+    /// Returns None for air and for solid voxels which were never exposed.
+    pub fn get_cube(&self, x: i32, y: i32, z: i32) -> Option<VoxelColor> {
+        if !self.in_bounds(x, y, z) {
+            return None;
+        }
+        self.columns[self.index(x, y)].colors.get(&(z as u8)).cloned()
+    }
+
+    fn is_exposed(&self, x: i32, y: i32, z: i32) -> bool {
+        !self.is_voxel_solid(&ivec3::new(x - 1, y, z)) || !self.is_voxel_solid(&ivec3::new(x + 1, y, z)) ||
+        !self.is_voxel_solid(&ivec3::new(x, y - 1, z)) || !self.is_voxel_solid(&ivec3::new(x, y + 1, z)) ||
+        !self.is_voxel_solid(&ivec3::new(x, y, z - 1)) || !self.is_voxel_solid(&ivec3::new(x, y, z + 1))
+    }
+
+    // --------------------------- VXL writing functions: ---------------------------
+
+    /// `Voxel::ColorFunction` uses the color set by `set_curcol`.
+    pub fn set_cube(&mut self, pos: &ivec3, voxel: Voxel) {
+        if !self.in_bounds(pos.x, pos.y, pos.z) {
+            return;
+        }
+        let index = self.index(pos.x, pos.y);
+        let col = match voxel {
+            Voxel::Air => None,
+            Voxel::Solid(col) => Some(col),
+            Voxel::ColorFunction => Some(self.cur_col),
+        };
+        match col {
+            None => self.columns[index].set_solid(pos.z, false),
+            Some(c) => {
+                self.columns[index].set_solid(pos.z, true);
+                self.columns[index].colors.insert(pos.z as u8, c);
+            }
+        }
+        self.update_colors(pos, pos);
+    }
+
+    pub fn set_sphere(&mut self, pos: &ivec3, radius: u32, operation_type: CsgOperationType) {
+        let r = radius as i32;
+        let r2 = r * r;
+        let solid = operation_type.is_insert();
+        self.fill(&(*pos - ivec3::new(r, r, r)), &(*pos + ivec3::new(r, r, r)), solid, |x, y, z| {
+            let d = ivec3::new(x, y, z) - *pos;
+            d.x * d.x + d.y * d.y + d.z * d.z <= r2
+        });
+    }
+
+    pub fn set_cylinder(&mut self, end_point1: &ivec3, end_point2: &ivec3, radius: u32, operation_type: CsgOperationType) {
+        let a = end_point1.to_vec3();
+        let axis = end_point2.to_vec3() - a;
+        let axis_len2 = axis.x * axis.x + axis.y * axis.y + axis.z * axis.z;
+        let r = radius as i32;
+        let r2 = (radius * radius) as f32;
+        let min = ivec3::new(cmp::min(end_point1.x, end_point2.x), cmp::min(end_point1.y, end_point2.y), cmp::min(end_point1.z, end_point2.z));
+        let max = ivec3::new(cmp::max(end_point1.x, end_point2.x), cmp::max(end_point1.y, end_point2.y), cmp::max(end_point1.z, end_point2.z));
+        let solid = operation_type.is_insert();
+        self.fill(&(min - ivec3::new(r, r, r)), &(max + ivec3::new(r, r, r)), solid, |x, y, z| {
+            let d = ivec3::new(x, y, z).to_vec3() - a;
+            let t = if axis_len2 > 0.0 {
+                (d.x * axis.x + d.y * axis.y + d.z * axis.z) / axis_len2
+            } else {
+                0.0
+            };
+            if !(0.0 ..= 1.0).contains(&t) {
+                return false;
+            }
+            let closest = d - axis * t;
+            closest.x * closest.x + closest.y * closest.y + closest.z * closest.z <= r2
+        });
+    }
+
+    pub fn set_rect(&mut self, p1: &ivec3, p2: &ivec3, mode: CsgOperationType) {
+        let min = ivec3::new(cmp::min(p1.x, p2.x), cmp::min(p1.y, p2.y), cmp::min(p1.z, p2.z));
+        let max = ivec3::new(cmp::max(p1.x, p2.x), cmp::max(p1.y, p2.y), cmp::max(p1.z, p2.z));
+        self.fill(&min, &max, mode.is_insert(), |_, _, _| true);
+    }
+
+    fn fill<F>(&mut self, min: &ivec3, max: &ivec3, solid: bool, inside: F)
+        where F: Fn(i32, i32, i32) -> bool {
+        let min = ivec3::new(cmp::max(min.x, 0), cmp::max(min.y, 0), cmp::max(min.z, 0));
+        let max = ivec3::new(cmp::min(max.x, self.width - 1), cmp::min(max.y, self.height - 1), cmp::min(max.z, MAX_Z - 1));
+        if min.x > max.x || min.y > max.y || min.z > max.z {
+            return;
+        }
+        for y in min.y ..= max.y {
+            for x in min.x ..= max.x {
+                let index = self.index(x, y);
+                for z in min.z ..= max.z {
+                    if inside(x, y, z) {
+                        self.columns[index].set_solid(z, solid);
+                    }
+                }
+            }
+        }
+        self.update_colors(&min, &max);
+    }
+
+    /// Gives a color to the voxels around the edited box which became exposed,
+    /// the way the engine calls vx5.colfunc for newly exposed voxels, and
+    /// drops the colors of the buried ones like the engine does.
+    fn update_colors(&mut self, min: &ivec3, max: &ivec3) {
+        let min = ivec3::new(cmp::max(min.x - 1, 0), cmp::max(min.y - 1, 0), cmp::max(min.z - 1, 0));
+        let max = ivec3::new(cmp::min(max.x + 1, self.width - 1), cmp::min(max.y + 1, self.height - 1), cmp::min(max.z + 1, MAX_Z - 1));
+        for y in min.y ..= max.y {
+            for x in min.x ..= max.x {
+                let index = self.index(x, y);
+                for z in min.z ..= max.z {
+                    if !self.columns[index].is_solid(z) {
+                        continue;
+                    }
+                    if !self.is_exposed(x, y, z) {
+                        self.columns[index].colors.remove(&(z as u8));
+                    } else if !self.columns[index].colors.contains_key(&(z as u8)) {
+                        let col = self.cur_col;
+                        self.columns[index].colors.insert(z as u8, col);
+                    }
+                }
+            }
+        }
+    }
+
+    // -------------------------  Physics helper functions: -------------------------
+
+    /// Walks the voxel grid from `pos` along `dir` until it hits a solid voxel
+    /// or leaves the map.
+    pub fn hitscan(&self, pos: &vec3, dir: &vec3) -> Option<WorldHitScanResult> {
+        if dir.len() == 0.0 {
+            return None;
+        }
+        let mut cell = ivec3::new(pos.x.floor() as i32, pos.y.floor() as i32, pos.z.floor() as i32);
+        if self.is_voxel_solid(&cell) {
+            return Some(WorldHitScanResult {
+                hit_face: None,
+                color: self.get_cube(cell.x, cell.y, cell.z),
+                pos: cell,
+            });
+        }
+        let step = ivec3::new(signum(dir.x), signum(dir.y), signum(dir.z));
+        let t_delta = vec3::new((1.0 / dir.x).abs(), (1.0 / dir.y).abs(), (1.0 / dir.z).abs());
+        let mut t_max = vec3::new(
+            first_boundary(pos.x, dir.x, cell.x),
+            first_boundary(pos.y, dir.y, cell.y),
+            first_boundary(pos.z, dir.z, cell.z));
+        loop {
+            let face = if t_max.x < t_max.y && t_max.x < t_max.z {
+                cell.x += step.x;
+                t_max.x += t_delta.x;
+                if step.x > 0 {CubeFace::XMin} else {CubeFace::XMax}
+            } else if t_max.y < t_max.z {
+                cell.y += step.y;
+                t_max.y += t_delta.y;
+                if step.y > 0 {CubeFace::YMin} else {CubeFace::YMax}
+            } else {
+                cell.z += step.z;
+                t_max.z += t_delta.z;
+                if step.z > 0 {CubeFace::ZMin} else {CubeFace::ZMax}
+            };
+            let outside = (cell.x < 0 && step.x <= 0) || (cell.x >= self.width && step.x >= 0) ||
+                (cell.y < 0 && step.y <= 0) || (cell.y >= self.height && step.y >= 0) ||
+                (cell.z < 0 && step.z <= 0);
+            if outside {
+                return None;
+            }
+            if self.is_voxel_solid(&cell) {
+                return Some(WorldHitScanResult {
+                    hit_face: Some(face),
+                    color: self.get_cube(cell.x, cell.y, cell.z),
+                    pos: cell,
+                });
+            }
+        }
+    }
+
+    /// Moves a sphere of radius `acr` by `move_vec`, sliding along walls
+    /// instead of stopping at them.
+    pub fn clip_move(&self, pos: &mut vec3, move_vec: &vec3, acr: f64) {
+        let radius = acr as f32;
+        let step_len = (radius * 0.5).max(0.1);
+        let steps = cmp::max((move_vec.len() / step_len).ceil() as i32, 1);
+        let step = *move_vec * (1.0 / steps as f32);
+        for _ in 0 .. steps {
+            let target = *pos + step;
+            if !self.sphere_collides(&target, radius) {
+                *pos = target;
+                continue;
+            }
+            // Slide: keep whatever part of the step is free along each axis
+            for axis_step in [vec3::new(step.x, 0.0, 0.0), vec3::new(0.0, step.y, 0.0), vec3::new(0.0, 0.0, step.z)].iter() {
+                let target = *pos + *axis_step;
+                if !self.sphere_collides(&target, radius) {
+                    *pos = target;
+                }
+            }
+        }
+    }
+
+    fn sphere_collides(&self, center: &vec3, radius: f32) -> bool {
+        let min = ivec3::new((center.x - radius).floor() as i32, (center.y - radius).floor() as i32, (center.z - radius).floor() as i32);
+        let max = ivec3::new((center.x + radius).floor() as i32, (center.y + radius).floor() as i32, (center.z + radius).floor() as i32);
+        for x in min.x ..= max.x {
+            for y in min.y ..= max.y {
+                for z in min.z ..= max.z {
+                    if !self.is_voxel_solid(&ivec3::new(x, y, z)) {
+                        continue;
+                    }
+                    let closest = vec3::new(
+                        center.x.max(x as f32).min((x + 1) as f32),
+                        center.y.max(y as f32).min((y + 1) as f32),
+                        center.z.max(z as f32).min((z + 1) as f32));
+                    if (closest - *center).len() < radius {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // -------------------------------- Engine upload: --------------------------------
+
+    /// Replaces the engine's map with this world, so rendering and the engine
+    /// side functions of `Voxlap` see it. Fails if the world is bigger than
+    /// the engine's VSID.
+    pub fn activate(&self, vox: &mut Voxlap) -> Result<(), ()> {
+        let vsid = vox.get_max_xy_dimension();
+        if self.width > vsid || self.height > vsid {
+            return Err(());
+        }
+        vox.set_rect(&ivec3::new(0, 0, 0), &ivec3::new(vsid - 1, vsid - 1, MAX_Z - 1), CsgOperationType::Remove);
+
+        // vspans can only address 256*256 columns, so upload the map in blocks
+        for block_y in (0 .. self.height).step_by(256) {
+            for block_x in (0 .. self.width).step_by(256) {
+                let mut spans = vec![];
+                for y in block_y .. cmp::min(block_y + 256, self.height) {
+                    for x in block_x .. cmp::min(block_x + 256, self.width) {
+                        let column = &self.columns[self.index(x, y)];
+                        if column.is_empty() {
+                            continue;
+                        }
+                        for (z0, z1) in column.runs() {
+                            spans.push(vspans {
+                                z0: z0 as u8,
+                                z1: z1 as u8,
+                                x: (x - block_x) as u8,
+                                y: (y - block_y) as u8,
+                            });
+                        }
+                    }
+                }
+                if !spans.is_empty() {
+                    vox.set_spans(&spans, &ivec3::new(block_x, block_y, 0), CsgOperationType::Insert);
+                }
+            }
+        }
+
+        for y in 0 .. self.height {
+            for x in 0 .. self.width {
+                for (&z, &col) in self.columns[self.index(x, y)].colors.iter() {
+                    vox.set_cube(&ivec3::new(x, y, z as i32), Voxel::Solid(col));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn signum(f: f32) -> i32 {
+    if f > 0.0 {1} else if f < 0.0 {-1} else {0}
+}
+
+fn first_boundary(pos: f32, dir: f32, cell: i32) -> f32 {
+    if dir > 0.0 {
+        ((cell + 1) as f32 - pos) / dir
+    } else if dir < 0.0 {
+        (cell as f32 - pos) / dir
+    } else {
+        f32::INFINITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world_with_floor(z: i32) -> World {
+        let mut world = World::new(8, 8).unwrap();
+        world.set_rect(&ivec3::new(0, 0, z), &ivec3::new(7, 7, z), CsgOperationType::Insert);
+        world
+    }
+
+    #[test]
+    fn new_rejects_too_many_columns() {
+        assert!(World::new(8, 8).is_ok());
+        assert!(World::new(65536, 65536).is_err());
+        assert!(World::new(u32::MAX, 1).is_err());
+    }
+
+    #[test]
+    fn buried_voxels_have_no_color() {
+        let mut world = World::new(8, 8).unwrap();
+        world.set_rect(&ivec3::new(2, 2, 2), &ivec3::new(4, 4, 4), CsgOperationType::Insert);
+        assert_eq!(world.get_cube(3, 3, 3), None);
+        assert!(world.get_cube(2, 2, 2).is_some());
+
+        world.set_cube(&ivec3::new(3, 3, 2), Voxel::Air);
+        assert_eq!(world.get_cube(3, 3, 3), Some(VoxelColor::rgb(0x80, 0x80, 0x80)));
+    }
+
+    #[test]
+    fn hitscan_hits_the_floor() {
+        let world = world_with_floor(10);
+        let hit = world.hitscan(&vec3::new(4.5, 4.5, 2.5), &vec3::new(0.0, 0.0, 1.0)).unwrap();
+        assert_eq!(hit.pos, ivec3::new(4, 4, 10));
+        assert_eq!(hit.hit_face, Some(CubeFace::ZMin));
+        assert!(hit.color.is_some());
+    }
+
+    #[test]
+    fn hitscan_misses_when_leaving_the_map() {
+        let world = world_with_floor(10);
+        assert!(world.hitscan(&vec3::new(4.5, 4.5, 2.5), &vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(world.hitscan(&vec3::new(4.5, 4.5, 2.5), &vec3::new(1.0, 0.0, 0.0)).is_none());
+        assert!(world.hitscan(&vec3::new(4.5, 4.5, 2.5), &vec3::new(0.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn hitscan_from_inside_a_voxel() {
+        let world = world_with_floor(10);
+        let hit = world.hitscan(&vec3::new(4.5, 4.5, 10.5), &vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert_eq!(hit.pos, ivec3::new(4, 4, 10));
+        assert_eq!(hit.hit_face, None);
+    }
+
+    #[test]
+    fn clip_move_stops_at_walls_and_slides() {
+        let mut world = World::new(8, 8).unwrap();
+        world.set_rect(&ivec3::new(6, 0, 0), &ivec3::new(6, 7, MAX_Z - 1), CsgOperationType::Insert);
+
+        let mut pos = vec3::new(3.5, 4.5, 4.5);
+        world.clip_move(&mut pos, &vec3::new(5.0, 0.0, 0.0), 0.4);
+        assert!(pos.x > 5.0 && pos.x <= 5.6, "x = {}", pos.x);
+        assert_eq!(pos.y, 4.5);
+
+        let mut pos = vec3::new(3.5, 4.5, 4.5);
+        world.clip_move(&mut pos, &vec3::new(5.0, 1.0, 0.0), 0.4);
+        assert!(pos.x <= 5.6, "x = {}", pos.x);
+        assert!((pos.y - 5.5).abs() < 0.001, "y = {}", pos.y);
+    }
+}